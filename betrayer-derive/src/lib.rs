@@ -0,0 +1,348 @@
+//! Derive macro for generating [betrayer](https://docs.rs/betrayer) menus from signal enums.
+//!
+//! Use it through the `derive` feature of betrayer instead of depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Expr, Fields, Ident, LitStr, Type, Variant};
+
+/// Generates a `fn menu(state: &State) -> Menu<Self>` that builds a menu with one entry per variant
+///
+/// The state type is set with `#[menu(state = Type)]` on the enum and defaults to `()`.
+///
+/// Every variant accepts the following options inside `#[menu(..)]`:
+/// - `label = "..."`: The label of the entry. Defaults to the name of the variant.
+/// - `submenu = "..."`: Places the entry in a submenu. Variants with the same submenu name share one submenu.
+/// - `range = expr`: Required for variants with a single field. Creates an entry for every value of the iterator
+///   and formats the value into the label, either at the `{}` placeholder or at the end. Other braces in the label are kept as they are.
+/// - `checked = field`: Turns the entry into a check button. For unit variants the field of the state has to be a `bool`,
+///   for `range` variants the entry is checked if the field is equal to the value.
+/// - `separator_before` / `separator_after`: Adds a separator before or after the entry.
+/// - `skip`: Leaves the variant out of the menu.
+///
+/// ```rust,ignore
+/// #[derive(Debug, Copy, Clone, TrayMenu)]
+/// #[menu(state = App)]
+/// enum Signal {
+///     #[menu(submenu = "Profiles", label = "Profile {}", range = 0..5, checked = selected)]
+///     Profile(u32),
+///     #[menu(separator_before)]
+///     Open,
+///     #[menu(label = "Quit")]
+///     Quit
+/// }
+///
+/// struct App {
+///     selected: u32
+/// }
+///
+/// let menu = Signal::menu(&App { selected: 0 });
+/// ```
+#[proc_macro_derive(TrayMenu, attributes(menu))]
+pub fn derive_tray_menu(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+enum Entry {
+    Items(TokenStream2),
+    Submenu(String, TokenStream2)
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(&input.ident, "`TrayMenu` can only be derived for enums"));
+    };
+    let state = parse_state(&input.attrs)?;
+
+    let mut entries = Vec::new();
+    for variant in &data.variants {
+        let options = ItemOptions::parse(variant)?;
+        if options.skip {
+            continue;
+        }
+        match &options.submenu {
+            Some(name) => {
+                let items = options.expand(variant, &Ident::new("children", name.span()))?;
+                let existing = entries.iter_mut().find_map(|entry| match entry {
+                    Entry::Submenu(existing, tokens) if *existing == name.value() => Some(tokens),
+                    _ => None
+                });
+                match existing {
+                    Some(tokens) => tokens.extend(items),
+                    None => entries.push(Entry::Submenu(name.value(), items))
+                }
+            }
+            None => entries.push(Entry::Items(options.expand(variant, &Ident::new("items", variant.span()))?))
+        }
+    }
+
+    let entries = entries.into_iter().map(|entry| match entry {
+        Entry::Items(tokens) => tokens,
+        Entry::Submenu(name, tokens) => quote! {
+            {
+                let mut children = ::std::vec::Vec::new();
+                #tokens
+                items.push(::betrayer::MenuItem::menu(#name, children));
+            }
+        }
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Builds the tray menu described by the `#[menu(..)]` attributes of the variants
+            #[allow(unused_variables)]
+            pub fn menu(state: &#state) -> ::betrayer::Menu<Self> {
+                let mut items = ::std::vec::Vec::new();
+                #(#entries)*
+                ::betrayer::Menu::new(items)
+            }
+        }
+    })
+}
+
+fn parse_state(attrs: &[Attribute]) -> syn::Result<Type> {
+    let mut state = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("menu")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("state") {
+                state = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown option, expected `state`"))
+            }
+        })?;
+    }
+    Ok(state.unwrap_or_else(|| parse_quote!(())))
+}
+
+#[derive(Default)]
+struct ItemOptions {
+    label: Option<LitStr>,
+    submenu: Option<LitStr>,
+    range: Option<Expr>,
+    checked: Option<Ident>,
+    separator_before: bool,
+    separator_after: bool,
+    skip: bool
+}
+
+impl ItemOptions {
+    fn parse(variant: &Variant) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in variant.attrs.iter().filter(|a| a.path().is_ident("menu")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("label") {
+                    options.label = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("submenu") {
+                    options.submenu = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("range") {
+                    options.range = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("checked") {
+                    options.checked = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("separator_before") {
+                    options.separator_before = true;
+                } else if meta.path.is_ident("separator_after") {
+                    options.separator_after = true;
+                } else if meta.path.is_ident("skip") {
+                    options.skip = true;
+                } else {
+                    return Err(meta.error(
+                        "unknown option, expected one of `label`, `submenu`, `range`, `checked`, `separator_before`, `separator_after` or `skip`"
+                    ));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(options)
+    }
+
+    fn expand(&self, variant: &Variant, target: &Ident) -> syn::Result<TokenStream2> {
+        let ident = &variant.ident;
+        let span = variant.span();
+        let item = match (&variant.fields, &self.range) {
+            (Fields::Unit, None) => {
+                let label = self
+                    .label
+                    .as_ref()
+                    .map(LitStr::value)
+                    .unwrap_or_else(|| ident.to_string());
+                match &self.checked {
+                    Some(field) => quote_spanned! {field.span()=>
+                        #target.push(::betrayer::MenuItem::check_button(#label, Self::#ident, state.#field));
+                    },
+                    None => quote_spanned! {span=>
+                        #target.push(::betrayer::MenuItem::button(#label, Self::#ident));
+                    }
+                }
+            }
+            (Fields::Unit, Some(range)) => return Err(Error::new_spanned(range, "`range` requires a variant with exactly one field")),
+            (Fields::Unnamed(fields), Some(range)) if fields.unnamed.len() == 1 => {
+                let label = match &self.label {
+                    Some(label) if label.value().contains("{}") => {
+                        let format = placeholder_format(label)?;
+                        quote!(::std::format!(#format, value))
+                    }
+                    Some(label) => quote!(::std::format!("{} {}", #label, value)),
+                    None => {
+                        let label = ident.to_string();
+                        quote!(::std::format!("{} {}", #label, value))
+                    }
+                };
+                let push = match &self.checked {
+                    Some(field) => quote_spanned! {field.span()=>
+                        let checked = state.#field == value;
+                        #target.push(::betrayer::MenuItem::check_button(label, Self::#ident(value), checked));
+                    },
+                    None => quote_spanned! {span=>
+                        #target.push(::betrayer::MenuItem::button(label, Self::#ident(value)));
+                    }
+                };
+                quote! {
+                    for value in #range {
+                        let label = #label;
+                        #push
+                    }
+                }
+            }
+            (Fields::Unnamed(_), None) => {
+                return Err(Error::new_spanned(
+                    &variant.fields,
+                    "variants with a field need a `range` to generate their values, or `skip`"
+                ))
+            }
+            (fields, _) => {
+                return Err(Error::new_spanned(
+                    fields,
+                    "only unit variants and variants with exactly one field are supported"
+                ))
+            }
+        };
+        let before = self
+            .separator_before
+            .then(|| quote!(#target.push(::betrayer::MenuItem::separator());));
+        let after = self
+            .separator_after
+            .then(|| quote!(#target.push(::betrayer::MenuItem::separator());));
+        Ok(quote! {
+            #before
+            #item
+            #after
+        })
+    }
+}
+
+/// Turns a label with a `{}` placeholder into a format string that only formats the value
+///
+/// All other braces are escaped so they show up in the label as written.
+fn placeholder_format(label: &LitStr) -> syn::Result<LitStr> {
+    let value = label.value();
+    let parts: Vec<_> = value.split("{}").collect();
+    if parts.len() > 2 {
+        return Err(Error::new_spanned(label, "labels can contain only one `{}` placeholder"));
+    }
+    let escaped: Vec<_> = parts
+        .iter()
+        .map(|part| part.replace('{', "{{").replace('}', "}}"))
+        .collect();
+    Ok(LitStr::new(&escaped.join("{}"), label.span()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(input: DeriveInput) -> String {
+        match expand(input) {
+            Ok(tokens) => panic!("expected an error, got {tokens}"),
+            Err(err) => err.to_string()
+        }
+    }
+
+    #[test]
+    fn placeholder_escapes_other_braces() {
+        let format = |label: &str| placeholder_format(&LitStr::new(label, proc_macro2::Span::call_site())).map(|lit| lit.value());
+        assert_eq!(format("Profile {}").unwrap(), "Profile {}");
+        assert_eq!(format("{x} {} }{").unwrap(), "{{x}} {} }}{{");
+        assert_eq!(format("{{}}").unwrap(), "{{{}}}");
+        assert!(format("{} of {}").is_err());
+    }
+
+    #[test]
+    fn formats_only_the_placeholder() {
+        let tokens = expand(parse_quote! {
+            enum Signal {
+                #[menu(label = "{Profile} {}", range = 0..2)]
+                Profile(u32)
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(tokens.contains(r#"format ! ("{{Profile}} {}" , value)"#), "{tokens}");
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let cases: [(DeriveInput, &str); 6] = [
+            (
+                parse_quote!(
+                    struct Signal;
+                ),
+                "only be derived for enums"
+            ),
+            (
+                parse_quote!(
+                    enum Signal {
+                        Profile(u32)
+                    }
+                ),
+                "need a `range`"
+            ),
+            (
+                parse_quote!(
+                    enum Signal {
+                        #[menu(range = 0..3)]
+                        Open
+                    }
+                ),
+                "exactly one field"
+            ),
+            (
+                parse_quote!(
+                    enum Signal {
+                        Pair { a: u32 }
+                    }
+                ),
+                "only unit variants"
+            ),
+            (
+                parse_quote!(
+                    enum Signal {
+                        #[menu(title = "Open")]
+                        Open
+                    }
+                ),
+                "unknown option"
+            ),
+            (
+                parse_quote!(
+                    enum Signal {
+                        #[menu(label = "{} of {}", range = 0..3)]
+                        Profile(u32)
+                    }
+                ),
+                "only one `{}`"
+            )
+        ];
+        for (input, expected) in cases {
+            let error = error(input);
+            assert!(error.contains(expected), "{error}");
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use betrayer::{Menu, MenuItem, TrayMenu};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, TrayMenu)]
+#[menu(state = App)]
+enum Signal {
+    #[menu(submenu = "Profiles", label = "Profile {}", range = 0..3, checked = selected)]
+    Profile(u32),
+    #[menu(submenu = "Profiles", separator_before)]
+    Reload,
+    #[menu(label = "Volume", range = [10, 20])]
+    Volume(u32),
+    #[menu(label = "{Level: {}}", range = 1..2)]
+    Level(u32),
+    #[menu(checked = muted, separator_after)]
+    Mute,
+    #[menu(skip)]
+    #[allow(dead_code)]
+    Hidden,
+    #[menu(label = "Quit {now}")]
+    Quit
+}
+
+struct App {
+    selected: u32,
+    muted: bool
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, TrayMenu)]
+enum Plain {
+    Open,
+    Close
+}
+
+#[test]
+fn builds_menu_from_variants() {
+    let menu = Signal::menu(&App { selected: 1, muted: true });
+    let expected = Menu::new([
+        MenuItem::menu(
+            "Profiles",
+            [
+                MenuItem::check_button("Profile 0", Signal::Profile(0), false),
+                MenuItem::check_button("Profile 1", Signal::Profile(1), true),
+                MenuItem::check_button("Profile 2", Signal::Profile(2), false),
+                MenuItem::separator(),
+                MenuItem::button("Reload", Signal::Reload)
+            ]
+        ),
+        MenuItem::button("Volume 10", Signal::Volume(10)),
+        MenuItem::button("Volume 20", Signal::Volume(20)),
+        MenuItem::button("{Level: 1}", Signal::Level(1)),
+        MenuItem::check_button("Mute", Signal::Mute, true),
+        MenuItem::separator(),
+        MenuItem::button("Quit {now}", Signal::Quit)
+    ]);
+    assert_eq!(menu, expected);
+}
+
+#[test]
+fn state_defaults_to_unit() {
+    assert_eq!(
+        Plain::menu(&()),
+        Menu::new([MenuItem::button("Open", Plain::Open), MenuItem::button("Close", Plain::Close)])
+    );
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 use betrayer::winit::WinitTrayIconBuilderExt;
-use betrayer::{Icon, Menu, MenuItem, TrayEvent, TrayIcon, TrayIconBuilder};
+use betrayer::{menu, Icon, Menu, TrayEvent, TrayIcon, TrayIconBuilder};
 use log::LevelFilter;
 use simple_logger::SimpleLogger;
 use winit::application::ApplicationHandler;
@@ -29,7 +29,7 @@ fn main() -> Result<()> {
         .with_tooltip("Demo System Tray")
         .with_menu(build_menu(selected))
         // with `winit` feature:
-        .build_event_loop(&event_loop, |e| Some(e))?;
+        .build_event_loop(&event_loop, Some)?;
     // without:
     //.build({
     //    let proxy = event_loop.create_proxy();
@@ -55,9 +55,13 @@ impl ApplicationHandler<TrayEvent<Signal>> for App {
                 Signal::Profile(i) => {
                     if self.selected != i {
                         self.selected = i;
-                        self.tray
-                            .set_tooltip(format!("Active Profile: {}", self.selected));
-                        self.tray.set_menu(build_menu(self.selected));
+                        let result = self
+                            .tray
+                            .set_tooltip(format!("Active Profile: {}", self.selected))
+                            .and_then(|_| self.tray.set_menu(build_menu(self.selected)));
+                        if let Err(err) = result {
+                            log::warn!("Failed to update tray: {err}");
+                        }
                     }
                 }
                 Signal::Open => {}
@@ -69,13 +73,14 @@ impl ApplicationHandler<TrayEvent<Signal>> for App {
 }
 
 fn build_menu(selected: u32) -> Menu<Signal> {
-    Menu::new([
-        MenuItem::menu(
-            "Profiles",
-            (0..5).map(|i| MenuItem::check_button(format!("Profile {}", i + 1), Signal::Profile(i), selected == i))
-        ),
-        MenuItem::separator(),
-        MenuItem::button("Open", Signal::Open),
-        MenuItem::button("Quit", Signal::Quit)
-    ])
+    menu![
+        menu("Profiles") {
+            for i in 0..5 {
+                check_button(format!("Profile {}", i + 1), Signal::Profile(i), selected == i)
+            }
+        },
+        separator,
+        button("Open", Signal::Open),
+        button("Quit", Signal::Quit)
+    ]
 }
@@ -0,0 +1,118 @@
+//! Integration with [calloop](https://docs.rs/calloop) event loops. *Linux only*.
+//!
+//! ```ignore
+//! let source = TrayIconBuilder::new()
+//!     .with_menu(menu![button("Quit", Signal::Quit)])
+//!     .build_calloop()?;
+//! event_loop.handle().insert_source(source, |event, tray, state| {
+//!     tray.set_tooltip(format!("Last event: {event:?}")).ok();
+//! })?;
+//! ```
+
+use std::error::Error;
+
+use ::calloop::channel::{channel, Channel, Event};
+use ::calloop::futures::{executor, Executor};
+use ::calloop::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+use crate::platform::NativeTrayIcon;
+use crate::{TrayError, TrayEvent, TrayIcon, TrayIconBuilder, TrayResult};
+
+pub trait CalloopTrayIconBuilderExt<T> {
+    /// Creates the tray icon as an event source for a calloop event loop.
+    ///
+    /// The DBus connection of the tray is driven by the event loop, so events are only delivered and
+    /// updates are only sent while the loop is dispatching.
+    /// Fails when called within a tokio runtime, as the connection would be driven by the runtime instead.
+    fn build_calloop(self) -> TrayResult<TrayEventSource<T>>;
+}
+
+impl<T: Clone + Send + 'static> CalloopTrayIconBuilderExt<T> for TrayIconBuilder<T> {
+    fn build_calloop(self) -> TrayResult<TrayEventSource<T>> {
+        let (sender, events) = channel();
+        let tray = TrayIcon(NativeTrayIcon::new_external(self, move |event| {
+            sender
+                .send(event)
+                .unwrap_or_else(|err| log::warn!("Failed to forward event: {}", err))
+        })?);
+
+        let (driver, scheduler) = executor().map_err(|err| TrayError::custom(format!("Failed to create the calloop executor: {err}")))?;
+        let connection = tray.0.executor().clone();
+        scheduler
+            .schedule(async move {
+                loop {
+                    connection.tick().await;
+                }
+            })
+            .map_err(|err| TrayError::custom(format!("Failed to schedule the DBus connection: {err}")))?;
+
+        Ok(TrayEventSource { tray, events, driver })
+    }
+}
+
+/// A calloop [EventSource] that emits the events of a tray icon
+///
+/// The tray icon is passed to the callback as metadata, so it can be updated from within the loop.
+pub struct TrayEventSource<T> {
+    tray: TrayIcon<T>,
+    events: Channel<TrayEvent<T>>,
+    driver: Executor<()>
+}
+
+impl<T> TrayEventSource<T> {
+    pub fn tray(&self) -> &TrayIcon<T> {
+        &self.tray
+    }
+}
+
+impl<T> EventSource for TrayEventSource<T> {
+    type Event = TrayEvent<T>;
+    type Metadata = TrayIcon<T>;
+    type Ret = ();
+    type Error = Box<dyn Error + Sync + Send>;
+
+    fn process_events<F>(&mut self, readiness: Readiness, token: Token, mut callback: F) -> Result<PostAction, Self::Error>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret
+    {
+        self.driver.process_events(readiness, token, |(), _| {})?;
+        let tray = &mut self.tray;
+        self.events.process_events(readiness, token, |event, _| {
+            if let Event::Msg(event) = event {
+                callback(event, tray);
+            }
+        })?;
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> ::calloop::Result<()> {
+        self.driver.register(poll, token_factory)?;
+        self.events.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> ::calloop::Result<()> {
+        self.driver.reregister(poll, token_factory)?;
+        self.events.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> ::calloop::Result<()> {
+        self.driver.unregister(poll)?;
+        self.events.unregister(poll)
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use crate::TrayErrorKind;
+
+    #[test]
+    fn rejects_tokio_runtime() {
+        let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+        let _guard = runtime.enter();
+        match TrayIconBuilder::<()>::new().build_calloop() {
+            Err(err) => assert_eq!(err.kind(), TrayErrorKind::Unsupported, "{err}"),
+            Ok(_) => panic!("the source was created within a tokio runtime")
+        }
+    }
+}
@@ -2,35 +2,100 @@ use std::borrow::Cow;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::panic::Location;
+use std::sync::Arc;
 
-use crate::platform::PlatformError;
+use crate::platform::{os_error_kind, PlatformError};
 
 pub type TrayResult<T> = Result<T, TrayError>;
 
 #[derive(Debug)]
 pub enum ErrorSource {
     Os(PlatformError),
-    Custom(Cow<'static, str>)
+    Custom(Cow<'static, str>),
+    /// An error returned by an async event handler
+    Handler(Box<dyn Error + Send + Sync>)
+}
+
+impl ErrorSource {
+    fn kind(&self) -> TrayErrorKind {
+        match self {
+            ErrorSource::Os(err) => os_error_kind(err),
+            ErrorSource::Custom(_) => TrayErrorKind::Other,
+            ErrorSource::Handler(_) => TrayErrorKind::Handler
+        }
+    }
+}
+
+/// What went wrong, independent of the platform
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum TrayErrorKind {
+    /// Nothing on the desktop can show tray icons
+    NoHost,
+    /// The icon data is malformed or doesn't match its dimensions
+    InvalidIcon,
+    /// The connection to the desktop could not be established or was lost
+    Connection,
+    /// The operation is not supported on this platform or with the enabled features
+    Unsupported,
+    /// An async event handler returned an error
+    Handler,
+    Other
 }
 
 pub struct TrayError {
     location: &'static Location<'static>,
-    source: ErrorSource
+    kind: TrayErrorKind,
+    // Boxed to keep results small, the OS errors are quite large
+    source: Box<ErrorSource>
 }
 
 impl TrayError {
     #[track_caller]
     pub fn custom(msg: impl Into<Cow<'static, str>>) -> Self {
+        Self::new(TrayErrorKind::Other, msg)
+    }
+
+    #[track_caller]
+    pub fn new(kind: TrayErrorKind, msg: impl Into<Cow<'static, str>>) -> Self {
         Self {
             location: Location::caller(),
-            source: ErrorSource::Custom(msg.into())
+            kind,
+            source: Box::new(ErrorSource::Custom(msg.into()))
         }
     }
 
-    pub fn source(&self) -> &ErrorSource {
+    #[track_caller]
+    pub fn handler(err: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self {
+            location: Location::caller(),
+            kind: TrayErrorKind::Handler,
+            source: Box::new(ErrorSource::Handler(err.into()))
+        }
+    }
+
+    /// Replaces the kind that was derived from the source of the error
+    pub(crate) fn with_kind(mut self, kind: TrayErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> TrayErrorKind {
+        self.kind
+    }
+
+    /// What caused the error
+    ///
+    /// Unlike [`Error::source`] this also exposes custom messages and OS errors on every platform.
+    pub fn error_source(&self) -> &ErrorSource {
         &self.source
     }
 
+    #[deprecated(since = "0.4.2", note = "shadows `Error::source`, use `error_source` instead")]
+    pub fn source(&self) -> &ErrorSource {
+        self.error_source()
+    }
+
     pub fn location(&self) -> &'static Location<'static> {
         self.location
     }
@@ -48,18 +113,63 @@ impl Display for TrayError {
     }
 }
 
-impl Error for TrayError {}
+impl Error for TrayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self.source.as_ref() {
+            #[cfg(not(target_os = "macos"))]
+            ErrorSource::Os(err) => Some(err),
+            #[cfg(target_os = "macos")]
+            ErrorSource::Os(_) => None,
+            ErrorSource::Custom(_) => None,
+            ErrorSource::Handler(err) => Some(err.as_ref())
+        }
+    }
+}
 
 impl<T: Into<ErrorSource>> From<T> for TrayError {
     #[track_caller]
     fn from(value: T) -> Self {
+        let source = value.into();
         Self {
             location: Location::caller(),
-            source: value.into()
+            kind: source.kind(),
+            source: Box::new(source)
         }
     }
 }
 
+/// Receives errors that happen in the background and can't be returned to the caller
+#[derive(Clone)]
+pub(crate) struct ErrorHook(Arc<dyn Fn(TrayError) + Send + Sync + 'static>);
+
+impl ErrorHook {
+    pub fn new<F: Fn(TrayError) + Send + Sync + 'static>(hook: F) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    /// Hands the error to the hook or logs it if there is none
+    pub fn report(hook: Option<&Self>, err: TrayError) {
+        match hook {
+            Some(hook) => (hook.0)(err),
+            None => log::error!("{err}")
+        }
+    }
+}
+
+impl Debug for ErrorHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ErrorHook").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for ErrorHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ErrorHook {}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! ensure {
@@ -69,3 +179,60 @@ macro_rules! ensure {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn custom_errors_have_no_source() {
+        let err = TrayError::custom("oops");
+        assert_eq!(err.kind(), TrayErrorKind::Other);
+        assert!(Error::source(&err).is_none());
+        assert!(matches!(err.error_source(), ErrorSource::Custom(msg) if msg == "oops"));
+
+        let err = TrayError::new(TrayErrorKind::NoHost, "no host");
+        assert_eq!(err.kind(), TrayErrorKind::NoHost);
+        assert!(Error::source(&err).is_none());
+    }
+
+    #[test]
+    fn handler_errors_expose_their_source() {
+        let err = TrayError::handler(io::Error::other("handler failed"));
+        assert_eq!(err.kind(), TrayErrorKind::Handler);
+        assert_eq!(Error::source(&err).unwrap().to_string(), "handler failed");
+        assert!(matches!(err.error_source(), ErrorSource::Handler(_)));
+    }
+
+    #[test]
+    fn with_kind_keeps_the_source() {
+        let err = TrayError::handler(io::Error::other("bad icon")).with_kind(TrayErrorKind::InvalidIcon);
+        assert_eq!(err.kind(), TrayErrorKind::InvalidIcon);
+        assert_eq!(Error::source(&err).unwrap().to_string(), "bad icon");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn os_errors_expose_their_source() {
+        let err = TrayError::from(zbus::Error::InputOutput(Arc::new(io::Error::other("broken pipe"))));
+        assert_eq!(err.kind(), TrayErrorKind::Connection);
+        assert!(Error::source(&err).is_some());
+        assert!(matches!(err.error_source(), ErrorSource::Os(_)));
+
+        let err = TrayError::from(zbus::Error::Unsupported);
+        assert_eq!(err.kind(), TrayErrorKind::Other);
+        assert!(Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn invalid_icons_are_reported() {
+        let err = crate::Icon::from_rgba(vec![0; 4], u32::MAX, u32::MAX).unwrap_err();
+        assert_eq!(err.kind(), TrayErrorKind::InvalidIcon);
+
+        let err = crate::Icon::from_rgba(vec![0; 3], 1, 1).unwrap_err();
+        assert_eq!(err.kind(), TrayErrorKind::InvalidIcon);
+        assert!(Error::source(&err).is_none());
+    }
+}
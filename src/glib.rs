@@ -0,0 +1,67 @@
+//! Integration with the [GLib](https://docs.rs/glib) main loop used by GTK applications. *Linux only*.
+
+use std::pin::pin;
+
+use ::glib::MainContext;
+use futures_util::future::select;
+
+use crate::platform::NativeTrayIcon;
+use crate::{forward_events, TrayError, TrayEvent, TrayIcon, TrayIconBuilder, TrayResult};
+
+pub trait GlibTrayIconBuilderExt<T> {
+    /// Creates the tray icon on a GLib main context, usually [MainContext::default].
+    ///
+    /// The DBus connection of the tray is driven by the context and `callback` runs on it,
+    /// so the returned [TrayIcon] can be updated directly from the callback or any other code on the context.
+    /// Has to be called from the thread that runs the context and fails within a tokio runtime, as the connection would be
+    /// driven by the runtime instead.
+    fn build_glib<F>(self, context: &MainContext, callback: F) -> TrayResult<TrayIcon<T>>
+    where
+        F: FnMut(TrayEvent<T>) + 'static;
+}
+
+impl<T: Clone + Send + 'static> GlibTrayIconBuilderExt<T> for TrayIconBuilder<T> {
+    fn build_glib<F>(self, context: &MainContext, mut callback: F) -> TrayResult<TrayIcon<T>>
+    where
+        F: FnMut(TrayEvent<T>) + 'static
+    {
+        let _guard = context
+            .acquire()
+            .map_err(|err| TrayError::custom(format!("The main context is owned by another thread: {err}")))?;
+
+        let (sender, events) = flume::unbounded();
+        let tray = TrayIcon(NativeTrayIcon::new_external(self, forward_events(sender))?);
+        let executor = tray.0.executor().clone();
+        context.spawn_local(async move {
+            let ticker = async {
+                loop {
+                    executor.tick().await;
+                }
+            };
+            // Stops driving the connection once the tray is gone and the event channel is closed
+            let dispatcher = async {
+                while let Ok(event) = events.recv_async().await {
+                    callback(event);
+                }
+            };
+            select(pin!(ticker), pin!(dispatcher)).await;
+        });
+        Ok(tray)
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use super::*;
+    use crate::TrayErrorKind;
+
+    #[test]
+    fn rejects_tokio_runtime() {
+        let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+        let _guard = runtime.enter();
+        match TrayIconBuilder::<()>::new().build_glib(&MainContext::new(), |_| {}) {
+            Err(err) => assert_eq!(err.kind(), TrayErrorKind::Unsupported, "{err}"),
+            Ok(_) => panic!("the tray was created within a tokio runtime")
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::error::Error;
+use std::future::Future;
+
+use flume::Receiver;
+use futures_util::StreamExt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::ErrorHook;
+use crate::{TrayError, TrayEvent};
+
+/// Controls how the handler of [TrayIconBuilder::build_with_async_handler](crate::TrayIconBuilder::build_with_async_handler) gets run
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum HandlerMode {
+    /// Runs one handler at a time. Events that arrive in the meantime are queued and handled in order.
+    #[default]
+    Sequential,
+    /// Starts a handler for every event as soon as it arrives, even if previous handlers are still running
+    Concurrent
+}
+
+/// Feeds the events from `events` into `handler` until the tray is dropped
+pub(crate) async fn run_handler<T, F, Fut, E>(events: Receiver<TrayEvent<T>>, mode: HandlerMode, mut handler: F, hook: Option<ErrorHook>)
+where
+    F: FnMut(TrayEvent<T>) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: Into<Box<dyn Error + Send + Sync>>
+{
+    let limit = match mode {
+        HandlerMode::Sequential => Some(1),
+        HandlerMode::Concurrent => None
+    };
+    let hook = hook.as_ref();
+    events
+        .into_stream()
+        .for_each_concurrent(limit, |event| {
+            let handler = handler(event);
+            async move {
+                if let Err(err) = handler.await {
+                    ErrorHook::report(hook, TrayError::handler(err));
+                }
+            }
+        })
+        .await
+}
+
+#[cfg(all(test, target_os = "linux", feature = "async-io"))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::platform::tests::{click, serve, wait_until};
+    use crate::{forward_events, Menu, MenuItem, TrayErrorKind, TrayIconBuilder};
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    /// Clicks two entries, the handler of the first one only finishes once it is released
+    fn handle_two_clicks(mode: HandlerMode) -> Vec<String> {
+        let (sender, receiver) = flume::unbounded();
+        let (release, released) = flume::bounded::<()>(1);
+        let log = Log::default();
+        let handler = {
+            let log = log.clone();
+            move |event| {
+                let (log, released) = (log.clone(), released.clone());
+                async move {
+                    let TrayEvent::Menu(id) = event else {
+                        return Ok::<_, TrayError>(());
+                    };
+                    log.lock().push(format!("start {id}"));
+                    if id == 1 {
+                        released.recv_async().await.unwrap();
+                    }
+                    log.lock().push(format!("end {id}"));
+                    Ok(())
+                }
+            }
+        };
+        let menu = Menu::new([MenuItem::button("a", 1), MenuItem::button("b", 2)]);
+        let (mut tray, peer) = serve(TrayIconBuilder::new().with_menu(menu), forward_events(sender));
+        tray.spawn_handler(run_handler(receiver, mode, handler, None))
+            .unwrap();
+
+        click(&peer, 1);
+        click(&peer, 2);
+        wait_until(|| !log.lock().is_empty());
+        std::thread::sleep(Duration::from_millis(50));
+        release.send(()).unwrap();
+        wait_until(|| log.lock().len() == 4);
+        let log = log.lock().clone();
+        log
+    }
+
+    #[test]
+    fn sequential_handlers_run_in_order() {
+        assert_eq!(handle_two_clicks(HandlerMode::Sequential), ["start 1", "end 1", "start 2", "end 2"]);
+    }
+
+    #[test]
+    fn concurrent_handlers_overlap() {
+        assert_eq!(handle_two_clicks(HandlerMode::Concurrent), ["start 1", "start 2", "end 2", "end 1"]);
+    }
+
+    #[test]
+    fn handler_errors_are_reported_to_the_hook() {
+        let (sender, receiver) = flume::unbounded();
+        let (report, reported) = flume::unbounded();
+        let hook = ErrorHook::new(move |err| report.send(err).unwrap());
+        let handler = |event| async move {
+            match event {
+                TrayEvent::Menu(2) => Err("failed"),
+                _ => Ok(())
+            }
+        };
+        let menu = Menu::new([MenuItem::button("a", 1), MenuItem::button("b", 2)]);
+        let (mut tray, peer) = serve(TrayIconBuilder::new().with_menu(menu), forward_events(sender));
+        tray.spawn_handler(run_handler(receiver, HandlerMode::Sequential, handler, Some(hook)))
+            .unwrap();
+
+        click(&peer, 1);
+        click(&peer, 2);
+        let err = reported.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(err.kind(), TrayErrorKind::Handler);
+        assert_eq!(std::error::Error::source(&err).unwrap().to_string(), "failed");
+        assert!(reported.is_empty());
+    }
+}
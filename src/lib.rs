@@ -1,21 +1,66 @@
-#![doc = include_str!("../Readme.md")]
+// The example of the Readme needs the `winit` feature, so it is only doctested with it
+#![cfg_attr(any(feature = "winit", not(doctest)), doc = include_str!("../Readme.md"))]
 
 mod error;
+mod handler;
+mod macros;
 mod platform;
+mod probe;
+mod run;
 mod utils;
 
+use std::error::Error;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use flume::TrySendError;
+use futures_core::Stream;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 #[cfg(feature = "winit")]
 pub mod winit;
 
-pub use error::{ErrorSource, TrayError, TrayResult};
+#[cfg(all(feature = "calloop", target_os = "linux"))]
+pub mod calloop;
+
+#[cfg(all(feature = "glib", target_os = "linux"))]
+pub mod glib;
+
+#[cfg(feature = "serde")]
+pub mod watch;
+
+#[cfg(feature = "derive")]
+pub use betrayer_derive::TrayMenu;
+use error::ErrorHook;
+pub use error::{ErrorSource, TrayError, TrayErrorKind, TrayResult};
+use handler::run_handler;
+pub use handler::HandlerMode;
 use platform::{NativeIcon, NativeTrayIcon};
+pub use probe::{is_supported, probe, probe_async, Support};
+pub use run::{run, run_with_init, RunControl, TimerId};
 
 /// Builder struct for a tray icon
+///
+/// With the `serde` feature the builder can be deserialized, for example from a config file.
+/// The icon is not part of the serialized form and has to be set with [TrayIconBuilder::with_icon].
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize), serde(default, bound(deserialize = "T: Deserialize<'de>")))]
 pub struct TrayIconBuilder<T = ()> {
     menu: Option<Menu<T>>,
     tooltip: Option<String>,
-    icon: Option<Icon>
+    #[cfg_attr(feature = "serde", serde(skip))]
+    icon: Option<Icon>,
+    auto_toggle: bool,
+    handler_mode: HandlerMode,
+    wait_for_watcher: bool,
+    cleanup_on_signal: bool,
+    icon_dir: Option<PathBuf>,
+    icon_cache_size: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    error_hook: Option<ErrorHook>
 }
 
 impl<T> Default for TrayIconBuilder<T> {
@@ -23,7 +68,14 @@ impl<T> Default for TrayIconBuilder<T> {
         Self {
             menu: None,
             tooltip: None,
-            icon: None
+            icon: None,
+            auto_toggle: false,
+            handler_mode: HandlerMode::default(),
+            wait_for_watcher: false,
+            cleanup_on_signal: false,
+            icon_dir: None,
+            icon_cache_size: 8,
+            error_hook: None
         }
     }
 }
@@ -52,6 +104,72 @@ impl<T> TrayIconBuilder<T> {
         self.icon = Some(icon);
         self
     }
+
+    /// Lets the tray flip the checkmark of [MenuItem::check_button] entries by itself when they are clicked.
+    ///
+    /// Clicks on check buttons are then reported as [TrayEvent::MenuToggled] with the new state instead of [TrayEvent::Menu].
+    pub fn with_auto_toggle(mut self, auto_toggle: bool) -> Self {
+        self.auto_toggle = auto_toggle;
+        self
+    }
+
+    /// Sets whether the handler of [TrayIconBuilder::build_with_async_handler] runs one event at a time or concurrently.
+    pub fn with_handler_mode(mut self, mode: HandlerMode) -> Self {
+        self.handler_mode = mode;
+        self
+    }
+
+    /// Lets the tray be created when no `StatusNotifierWatcher` is running yet instead of failing. *Linux only*.
+    ///
+    /// The tray is then registered as soon as a watcher appears and reports [TrayEvent::HostAvailable] once it is shown.
+    /// Use [probe] to find out up front whether trays can be shown at all.
+    pub fn with_wait_for_watcher(mut self, wait: bool) -> Self {
+        self.wait_for_watcher = wait;
+        self
+    }
+
+    /// Deletes the icon files of the process when it is terminated by `SIGINT` or `SIGTERM`. *Linux only*.
+    ///
+    /// Icons are passed to the tray host as files, which would otherwise stay behind until the next start
+    /// removes them. The signals still terminate the process, [run] stops its loop on them as before.
+    /// If the application handled a signal before the first tray was created, only the icons are removed and its handler
+    /// decides whether the process exits. Handlers that are installed later can't be detected, the process still terminates then.
+    /// The handlers stay installed for the rest of the process, see [run] for how they interact with handlers of the application.
+    pub fn with_cleanup_on_signal(mut self, cleanup: bool) -> Self {
+        self.cleanup_on_signal = cleanup;
+        self
+    }
+
+    /// Sets the directory for the icon files that are passed to the tray host. *Linux only*.
+    ///
+    /// By default `$XDG_RUNTIME_DIR/betrayer` is used, or a private `betrayer-{uid}` directory in the temp directory
+    /// when there is no runtime directory. The directory is created if necessary and must be owned by the current user
+    /// and not be writable by others. Icons are passed as pixels when the path is not valid UTF-8.
+    pub fn with_icon_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.icon_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets how many icons are kept around to be reused when [TrayIcon::set_icon] is called with a known icon again. *Linux only*.
+    ///
+    /// Switching between a few icons, for example to show a connection state, then neither writes nor decodes them again.
+    /// The current icon is always kept. Defaults to 8.
+    pub fn with_icon_cache_size(mut self, size: usize) -> Self {
+        self.icon_cache_size = size;
+        self
+    }
+
+    /// Sets a hook that receives errors that can't be returned to the caller, like the errors of async event handlers
+    /// or of tray updates that are applied in the background.
+    ///
+    /// Without a hook these errors are logged.
+    pub fn with_error_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(TrayError) + Send + Sync + 'static
+    {
+        self.error_hook = Some(ErrorHook::new(hook));
+        self
+    }
 }
 
 impl<T: Clone + Send + 'static> TrayIconBuilder<T> {
@@ -62,26 +180,379 @@ impl<T: Clone + Send + 'static> TrayIconBuilder<T> {
     {
         Ok(TrayIcon(NativeTrayIcon::new(self, callback)?))
     }
+
+    /// Like [TrayIconBuilder::build], but doesn't block the current thread while the tray is being set up.
+    ///
+    /// Only *Linux* sets up the tray asynchronously; the other platforms complete immediately.
+    pub async fn build_async<F>(self, callback: F) -> TrayResult<TrayIcon<T>>
+    where
+        F: FnMut(TrayEvent<T>) + Send + 'static
+    {
+        Ok(TrayIcon(NativeTrayIcon::new_async(self, callback).await?))
+    }
+
+    /// Creates the tray icon and runs the future returned by `handler` for every event.
+    ///
+    /// On *Linux* the handlers run on the executor of the DBus connection, the other platforms run them on a dedicated thread.
+    /// Whether handlers can overlap is controlled by [TrayIconBuilder::with_handler_mode] and errors are reported to the
+    /// [error hook](TrayIconBuilder::with_error_hook). Handlers that are still running when the tray is dropped may be cancelled.
+    pub fn build_with_async_handler<F, Fut, E>(self, handler: F) -> TrayResult<TrayIcon<T>>
+    where
+        F: FnMut(TrayEvent<T>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<Box<dyn Error + Send + Sync>> + 'static
+    {
+        let mode = self.handler_mode;
+        let hook = self.error_hook.clone();
+        let (sender, receiver) = flume::unbounded();
+        let mut tray = self.build(forward_events(sender))?;
+        tray.0
+            .spawn_handler(run_handler(receiver, mode, handler, hook))?;
+        Ok(tray)
+    }
+
+    /// Creates the tray icon and delivers its events through an unbounded channel instead of a callback.
+    pub fn build_channel(self) -> TrayResult<(TrayIcon<T>, flume::Receiver<TrayEvent<T>>)> {
+        let (sender, receiver) = flume::unbounded();
+        Ok((self.build(forward_events(sender))?, receiver))
+    }
+
+    /// Like [TrayIconBuilder::build_channel], but the channel holds at most `capacity` events.
+    ///
+    /// When the channel is full, new events are dropped with a warning instead of blocking the thread that reports them.
+    pub fn build_channel_bounded(self, capacity: usize) -> TrayResult<(TrayIcon<T>, flume::Receiver<TrayEvent<T>>)> {
+        let (sender, receiver) = flume::bounded(capacity);
+        Ok((self.build(forward_events(sender))?, receiver))
+    }
+
+    /// Creates the tray icon and delivers its events as an async [Stream] that is never full.
+    pub fn build_stream(self) -> TrayResult<(TrayIcon<T>, impl Stream<Item = TrayEvent<T>>)> {
+        let (tray, receiver) = self.build_channel()?;
+        Ok((tray, receiver.into_stream()))
+    }
+
+    /// Like [TrayIconBuilder::build_stream], but buffers at most `capacity` events.
+    ///
+    /// When the buffer is full, new events are dropped with a warning until the stream is polled again.
+    pub fn build_stream_bounded(self, capacity: usize) -> TrayResult<(TrayIcon<T>, impl Stream<Item = TrayEvent<T>>)> {
+        let (tray, receiver) = self.build_channel_bounded(capacity)?;
+        Ok((tray, receiver.into_stream()))
+    }
+
+    /// Serves the tray on an existing DBus connection instead of opening a new one. *Linux only*.
+    ///
+    /// The item and its menu are served at unique object paths, so the connection can be shared with other trays and objects.
+    /// The background tasks of the tray are spawned on the executor of the connection, so they only make progress while that executor is running.
+    /// The objects are removed from the connection when the tray is dropped.
+    #[cfg(target_os = "linux")]
+    pub async fn build_on_connection<F>(self, connection: &zbus::Connection, callback: F) -> TrayResult<TrayIcon<T>>
+    where
+        F: FnMut(TrayEvent<T>) + Send + 'static
+    {
+        Ok(TrayIcon(NativeTrayIcon::new_on_connection(connection, self, callback).await?))
+    }
 }
 
+pub(crate) fn forward_events<T>(sender: flume::Sender<TrayEvent<T>>) -> impl FnMut(TrayEvent<T>) + Send + 'static
+where
+    T: Send + 'static
+{
+    move |event| match sender.try_send(event) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => log::warn!("Dropping tray event because the event channel is full"),
+        Err(TrySendError::Disconnected(_)) => log::trace!("Dropping tray event because the event receiver is gone")
+    }
+}
+
+/// A tray icon that is removed when dropped
+///
+/// The setters return an error when the change can't be applied. On *Linux* changes are applied in the background,
+/// so failures that happen there are reported to the [error hook](TrayIconBuilder::with_error_hook) instead.
+/// The `_async` variants wait for the change to be applied and return these errors directly.
+///
+/// On *Linux* the tray can be updated from other threads through a [TrayHandle].
 pub struct TrayIcon<T>(NativeTrayIcon<T>);
 
 impl<T> TrayIcon<T> {
     /// Updates or removes the tooltip
-    pub fn set_tooltip<S: ToString>(&self, tooltip: impl Into<Option<S>>) {
+    pub fn set_tooltip<S: ToString>(&self, tooltip: impl Into<Option<S>>) -> TrayResult<()> {
         self.0.set_tooltip(tooltip.into().map(|s| s.to_string()))
     }
 
-    pub fn set_icon(&self, icon: impl Into<Option<Icon>>) {
+    /// Updates or removes the icon
+    pub fn set_icon(&self, icon: impl Into<Option<Icon>>) -> TrayResult<()> {
         self.0.set_icon(icon.into())
     }
+
+    /// Like [TrayIcon::set_tooltip], but resolves once the tray host has been notified about the change.
+    pub async fn set_tooltip_async<S: ToString>(&self, tooltip: impl Into<Option<S>>) -> TrayResult<()> {
+        self.0
+            .set_tooltip_async(tooltip.into().map(|s| s.to_string()))
+            .await
+    }
+
+    /// Like [TrayIcon::set_icon], but resolves once the tray host has been notified about the change.
+    pub async fn set_icon_async(&self, icon: impl Into<Option<Icon>>) -> TrayResult<()> {
+        self.0.set_icon_async(icon.into()).await
+    }
+}
+
+impl<T> TrayIcon<T> {
+    /// Returns a handle that can update the tray from other threads. *Linux only*.
+    #[cfg(target_os = "linux")]
+    pub fn handle(&self) -> TrayHandle<T> {
+        TrayHandle(self.0.handle())
+    }
+
+    /// The tooltip that is currently published, exactly as it was set
+    ///
+    /// On *Linux* changes that are applied in the background only show up here once they are published.
+    /// *MacOS* doesn't show tooltips yet, the tooltip is still reported here.
+    pub fn tooltip(&self) -> Option<String> {
+        self.0.tooltip()
+    }
+
+    /// Whether the tray can currently be seen
+    ///
+    /// On *Windows* the tray has no host while the taskbar didn't take it back after a restart of Explorer.
+    /// On *MacOS* the tray has no host while the user hid it from the menu bar.
+    pub fn status(&self) -> TrayStatus {
+        self.0.status()
+    }
+}
+
+impl<T: Clone + 'static> TrayIcon<T> {
+    /// The menu that is currently published, `None` if it was removed or never set
+    ///
+    /// Check buttons report their current state, including changes made by [TrayIconBuilder::with_auto_toggle].
+    pub fn menu(&self) -> Option<Menu<T>> {
+        self.0.menu()
+    }
+
+    /// Everything the tray currently publishes
+    pub fn snapshot(&self) -> TraySnapshot<T> {
+        self.0.snapshot()
+    }
+}
+
+impl<T: Clone + Send + 'static> TrayIcon<T> {
+    /// Removes the tray icon and reports when that fails
+    ///
+    /// Dropping the tray removes it as well, but can only log errors.
+    pub async fn shutdown(self) -> TrayResult<()> {
+        self.0.shutdown().await
+    }
+
+    /// Blocking version of [TrayIcon::shutdown]
+    pub fn close(self) -> TrayResult<()> {
+        self.0.close()
+    }
 }
 
 impl<T: 'static> TrayIcon<T> {
     /// Updates or removes the menu
-    pub fn set_menu(&self, menu: impl Into<Option<Menu<T>>>) {
+    pub fn set_menu(&self, menu: impl Into<Option<Menu<T>>>) -> TrayResult<()> {
         self.0.set_menu(menu.into())
     }
+
+    /// Like [TrayIcon::set_menu], but resolves once the tray host has been notified about the change.
+    pub async fn set_menu_async(&self, menu: impl Into<Option<Menu<T>>>) -> TrayResult<()> {
+        self.0.set_menu_async(menu.into()).await
+    }
+
+    /// Applies several changes at once
+    ///
+    /// ```no_run
+    /// # use betrayer::{Icon, Menu, MenuItem, TrayIcon};
+    /// # fn example(tray: &TrayIcon<()>, icon: Icon) -> betrayer::TrayResult<()> {
+    /// tray.update(|tx| {
+    ///     tx.icon(icon);
+    ///     tx.tooltip("Connected");
+    ///     tx.menu(Menu::new([MenuItem::button("Disconnect", ())]));
+    /// })
+    /// # }
+    /// ```
+    ///
+    /// On *Linux* the host only learns about the changes once all of them are in place. Changes that are still waiting
+    /// to be applied are merged with newer ones, so a burst of updates only sends the final state to the host.
+    pub fn update(&self, changes: impl FnOnce(&mut TrayChanges<T>)) -> TrayResult<()> {
+        let mut tx = TrayChanges::default();
+        changes(&mut tx);
+        self.0.update(tx)
+    }
+
+    /// Like [TrayIcon::update], but resolves once the tray host has been notified about the changes.
+    pub async fn update_async(&self, changes: impl FnOnce(&mut TrayChanges<T>)) -> TrayResult<()> {
+        let mut tx = TrayChanges::default();
+        changes(&mut tx);
+        self.0.update_async(tx).await
+    }
+}
+
+/// A handle that updates a [TrayIcon] from any thread. *Linux only*.
+///
+/// Handles are cheap to clone and can be shared with background workers. They don't keep the tray alive,
+/// once the tray is dropped every update fails.
+///
+/// The other platforms have no handle, as the tray has to be updated from the thread that created it:
+/// On *Windows* the tray belongs to a message window, which only the thread that created it may use,
+/// and on *MacOS* AppKit may only be used from the main thread.
+#[cfg(target_os = "linux")]
+pub struct TrayHandle<T>(platform::NativeTrayHandle<T>);
+
+// Handles exist to be sent to other threads, this fails to compile if one of their fields stops being thread-safe
+#[cfg(target_os = "linux")]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<TrayHandle<u8>>();
+};
+
+#[cfg(target_os = "linux")]
+impl<T> Clone for TrayHandle<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<T> TrayHandle<T> {
+    /// Like [TrayIcon::set_tooltip]
+    pub fn set_tooltip<S: ToString>(&self, tooltip: impl Into<Option<S>>) -> TrayResult<()> {
+        self.0.set_tooltip(tooltip.into().map(|s| s.to_string()))
+    }
+
+    /// Like [TrayIcon::set_icon]
+    pub fn set_icon(&self, icon: impl Into<Option<Icon>>) -> TrayResult<()> {
+        self.0.set_icon(icon.into())
+    }
+
+    /// Like [TrayIcon::set_menu]
+    pub fn set_menu(&self, menu: impl Into<Option<Menu<T>>>) -> TrayResult<()> {
+        self.0.set_menu(menu.into())
+    }
+
+    /// Like [TrayIcon::update]
+    pub fn update(&self, changes: impl FnOnce(&mut TrayChanges<T>)) -> TrayResult<()> {
+        let mut tx = TrayChanges::default();
+        changes(&mut tx);
+        self.0.update(tx)
+    }
+
+    /// Like [TrayIcon::set_tooltip_async]
+    pub async fn set_tooltip_async<S: ToString>(&self, tooltip: impl Into<Option<S>>) -> TrayResult<()> {
+        self.0
+            .set_tooltip_async(tooltip.into().map(|s| s.to_string()))
+            .await
+    }
+
+    /// Like [TrayIcon::set_icon_async]
+    pub async fn set_icon_async(&self, icon: impl Into<Option<Icon>>) -> TrayResult<()> {
+        self.0.set_icon_async(icon.into()).await
+    }
+
+    /// Like [TrayIcon::set_menu_async]
+    pub async fn set_menu_async(&self, menu: impl Into<Option<Menu<T>>>) -> TrayResult<()> {
+        self.0.set_menu_async(menu.into()).await
+    }
+
+    /// Like [TrayIcon::update_async]
+    pub async fn update_async(&self, changes: impl FnOnce(&mut TrayChanges<T>)) -> TrayResult<()> {
+        let mut tx = TrayChanges::default();
+        changes(&mut tx);
+        self.0.update_async(tx).await
+    }
+
+    /// Like [TrayIcon::tooltip]
+    pub fn tooltip(&self) -> Option<String> {
+        self.0.tooltip()
+    }
+
+    /// Like [TrayIcon::status]
+    pub fn status(&self) -> TrayStatus {
+        self.0.status()
+    }
+
+    /// Reports an error to the [error hook](TrayIconBuilder::with_error_hook) of the tray
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
+    pub(crate) fn report(&self, err: TrayError) {
+        self.0.report(err)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<T: Clone> TrayHandle<T> {
+    /// Like [TrayIcon::menu]
+    pub fn menu(&self) -> Option<Menu<T>> {
+        self.0.menu()
+    }
+
+    /// Like [TrayIcon::snapshot]
+    pub fn snapshot(&self) -> TraySnapshot<T> {
+        self.0.snapshot()
+    }
+}
+
+/// A set of changes that [TrayIcon::update] applies together
+///
+/// Everything that isn't changed keeps its current value. Later calls replace earlier ones.
+pub struct TrayChanges<T> {
+    tooltip: Option<Option<String>>,
+    icon: Option<Option<Icon>>,
+    menu: Option<Option<Menu<T>>>
+}
+
+impl<T> Default for TrayChanges<T> {
+    fn default() -> Self {
+        Self {
+            tooltip: None,
+            icon: None,
+            menu: None
+        }
+    }
+}
+
+impl<T> TrayChanges<T> {
+    /// Updates or removes the tooltip
+    pub fn tooltip<S: ToString>(&mut self, tooltip: impl Into<Option<S>>) -> &mut Self {
+        self.tooltip = Some(tooltip.into().map(|s| s.to_string()));
+        self
+    }
+
+    /// Updates or removes the icon
+    pub fn icon(&mut self, icon: impl Into<Option<Icon>>) -> &mut Self {
+        self.icon = Some(icon.into());
+        self
+    }
+
+    /// Updates or removes the menu
+    pub fn menu(&mut self, menu: impl Into<Option<Menu<T>>>) -> &mut Self {
+        self.menu = Some(menu.into());
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    fn from_tooltip(tooltip: Option<String>) -> Self {
+        Self {
+            tooltip: Some(tooltip),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn from_icon(icon: Option<Icon>) -> Self {
+        Self {
+            icon: Some(icon),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn from_menu(menu: Option<Menu<T>>) -> Self {
+        Self {
+            menu: Some(menu),
+            ..Default::default()
+        }
+    }
 }
 
 /// Enum for describing how a user clicked on the tray icon
@@ -105,16 +576,116 @@ pub enum ClickType {
 /// See the docs of [ClickType] for platform specific notes about mouse click events
 ///
 /// The parameter of the [TrayEvent::Menu] variant is a copy of the signal token assigned to the respective [MenuItem]
+///
+/// [TrayEvent::MenuToggled] is only emitted when [TrayIconBuilder::with_auto_toggle] is enabled and carries the new checkmark state
+///
+/// [TrayEvent::Connection] is only emitted on *Linux*, see [ConnectionState]
+///
+/// [TrayEvent::HostAvailable] and [TrayEvent::HostUnavailable] are only emitted on *Linux* when a tray host (usually the panel of the desktop)
+/// appears or goes away after the tray was created. While no host is available the tray is not visible.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum TrayEvent<T> {
     Tray(ClickType),
-    Menu(T)
+    Menu(T),
+    MenuToggled(T, bool),
+    Connection(ConnectionState),
+    HostAvailable,
+    HostUnavailable
+}
+
+/// The state of the connection between the tray and the desktop
+///
+/// On *Linux* trays created with [TrayIconBuilder::build] or [TrayIconBuilder::build_async] reconnect to the session bus
+/// when the connection is lost and publish their current icon, tooltip and menu again.
+/// The tray is not visible between [ConnectionState::Disconnected] and [ConnectionState::Reconnected].
+/// Trays that are driven by an existing connection or event loop don't reconnect.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConnectionState {
+    Disconnected,
+    Reconnected
+}
+
+/// Whether a tray can currently be seen, as returned by [TrayIcon::status]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "snake_case"))]
+pub enum TrayStatus {
+    /// A tray host shows the tray
+    Visible,
+    /// The tray is published, but no tray host is running to show it
+    NoHost,
+    /// The connection to the session bus is lost, the tray is published again once it is restored. *Linux only*.
+    Disconnected
+}
+
+/// Everything a tray currently publishes, as returned by [TrayIcon::snapshot]
+///
+/// With the `serde` feature the snapshot can be serialized, for example to log it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(bound(deserialize = "T: Deserialize<'de>")))]
+pub struct TraySnapshot<T> {
+    pub tooltip: Option<String>,
+    pub icon: Option<SnapshotIcon>,
+    pub menu: Option<Vec<SnapshotItem<T>>>,
+    pub status: TrayStatus
+}
+
+/// The icon of a [TraySnapshot]
+///
+/// *MacOS* doesn't show icons yet, so its snapshots have no icon.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(tag = "type", rename_all = "snake_case"))]
+pub enum SnapshotIcon {
+    /// An icon the tray host looks up by name or path, on *Windows* an embedded resource named `#{id}`
+    Named { name: String },
+    /// An image, identified by a hash of its png data, or of its RGBA data on *Windows*
+    ///
+    /// The size is `None` if the png header can't be read.
+    Image { hash: u64, size: Option<(u32, u32)> }
+}
+
+/// A menu entry of a [TraySnapshot]
+///
+/// Mirrors [MenuItem], but keeps only the label of actions so the whole snapshot can be (de)serialized.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(tag = "type", rename_all = "snake_case"))]
+pub enum SnapshotItem<T> {
+    Separator,
+    Button {
+        name: String,
+        signal: T,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+        checked: Option<bool>
+    },
+    Action {
+        name: String
+    },
+    Menu {
+        name: String,
+        children: Vec<SnapshotItem<T>>
+    }
+}
+
+impl<T> From<MenuItem<T>> for SnapshotItem<T> {
+    fn from(value: MenuItem<T>) -> Self {
+        match value {
+            MenuItem::Separator => Self::Separator,
+            MenuItem::Button { name, signal, checked } => Self::Button { name, signal, checked },
+            MenuItem::Action { name, .. } => Self::Action { name },
+            MenuItem::Menu { name, children } => Self::Menu {
+                name,
+                children: children.into_iter().map(Self::from).collect()
+            }
+        }
+    }
 }
 
 /// A struct describing the layout of a tray icon menu
 ///
 /// The actual Menus are created lazily by the [TrayIcon].
+///
+/// With the `serde` feature a menu is (de)serialized as a list of [MenuItem]s.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
 pub struct Menu<T> {
     items: Vec<MenuItem<T>>
 }
@@ -134,14 +705,160 @@ impl<T> Menu<T> {
     pub fn empty() -> Self {
         Self { items: Vec::new() }
     }
+
+    /// Combines multiple menus into one, placing a separator between each non-empty section
+    pub fn merge<I>(sections: I) -> Self
+    where
+        I: IntoIterator<Item = Menu<T>>
+    {
+        let mut menu = Self::empty();
+        for section in sections.into_iter().filter(|s| !s.is_empty()) {
+            if !menu.is_empty() {
+                menu.push(MenuItem::separator());
+            }
+            menu.extend(section);
+        }
+        menu
+    }
+
+    /// Converts the signals of all items, including the ones in submenus
+    ///
+    /// This allows embedding a menu with a different signal type into another menu.
+    pub fn map<U, F>(self, mut f: F) -> Menu<U>
+    where
+        F: FnMut(T) -> U
+    {
+        Menu {
+            items: self
+                .items
+                .into_iter()
+                .map(|item| item.map_with(&mut f))
+                .collect()
+        }
+    }
+
+    /// The top-level items of this menu
+    pub fn items(&self) -> &[MenuItem<T>] {
+        &self.items
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Appends an item to the end of the menu
+    pub fn push(&mut self, item: MenuItem<T>) {
+        self.items.push(item);
+    }
+
+    /// Inserts an item at position `index`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, item: MenuItem<T>) {
+        self.items.insert(index, item);
+    }
+
+    /// Searches the menu depth-first, including all submenus, and returns the first item that matches the predicate
+    pub fn find<P>(&self, mut predicate: P) -> Option<&MenuItem<T>>
+    where
+        P: FnMut(&MenuItem<T>) -> bool
+    {
+        find_item(&self.items, &mut predicate)
+    }
+
+    /// Like [Menu::find], but returns a mutable reference to the item
+    pub fn find_mut<P>(&mut self, mut predicate: P) -> Option<&mut MenuItem<T>>
+    where
+        P: FnMut(&MenuItem<T>) -> bool
+    {
+        find_item_mut(&mut self.items, &mut predicate)
+    }
+}
+
+impl<T> Default for Menu<T> {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl<T> Extend<MenuItem<T>> for Menu<T> {
+    fn extend<I: IntoIterator<Item = MenuItem<T>>>(&mut self, iter: I) {
+        self.items.extend(iter)
+    }
+}
+
+impl<T> FromIterator<MenuItem<T>> for Menu<T> {
+    fn from_iter<I: IntoIterator<Item = MenuItem<T>>>(iter: I) -> Self {
+        Self::new(iter)
+    }
+}
+
+impl<T> IntoIterator for Menu<T> {
+    type Item = MenuItem<T>;
+    type IntoIter = std::vec::IntoIter<MenuItem<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+fn find_item<'a, T>(items: &'a [MenuItem<T>], predicate: &mut impl FnMut(&MenuItem<T>) -> bool) -> Option<&'a MenuItem<T>> {
+    for item in items {
+        if predicate(item) {
+            return Some(item);
+        }
+        if let MenuItem::Menu { children, .. } = item {
+            if let Some(found) = find_item(children, predicate) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+fn find_item_mut<'a, T>(items: &'a mut [MenuItem<T>], predicate: &mut impl FnMut(&MenuItem<T>) -> bool) -> Option<&'a mut MenuItem<T>> {
+    for item in items {
+        if predicate(item) {
+            return Some(item);
+        }
+        if let MenuItem::Menu { children, .. } = item {
+            if let Some(found) = find_item_mut(children, predicate) {
+                return Some(found);
+            }
+        }
+    }
+    None
 }
 
 /// Various menu items that can be added to a [Menu]
+///
+/// With the `serde` feature items are tagged with their `type`, for example `{ type = "button", name = "Quit", signal = "quit" }`.
+/// [MenuItem::Action] can't be (de)serialized, a [TraySnapshot] keeps only its label.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(tag = "type", rename_all = "snake_case"))]
 pub enum MenuItem<T> {
     Separator,
-    Button { name: String, signal: T, checked: Option<bool> },
-    Menu { name: String, children: Vec<MenuItem<T>> }
+    Button {
+        name: String,
+        signal: T,
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+        checked: Option<bool>
+    },
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Action {
+        name: String,
+        action: MenuAction
+    },
+    Menu {
+        name: String,
+        children: Vec<MenuItem<T>>
+    }
 }
 
 impl<T> MenuItem<T> {
@@ -174,6 +891,20 @@ impl<T> MenuItem<T> {
         }
     }
 
+    /// A new clickable entry with label that runs `action` when clicked instead of emitting a [TrayEvent]
+    ///
+    /// On *Linux* the closure runs on the DBus thread, on *Windows* and *Mac* it runs on the thread of the event loop.
+    pub fn action<S, F>(name: S, action: F) -> Self
+    where
+        S: ToString,
+        F: Fn() + Send + Sync + 'static
+    {
+        Self::Action {
+            name: name.to_string(),
+            action: MenuAction::new(action)
+        }
+    }
+
     /// A new submenu
     pub fn menu<S, I>(name: S, children: I) -> Self
     where
@@ -185,6 +916,86 @@ impl<T> MenuItem<T> {
             children: children.into_iter().collect()
         }
     }
+
+    /// The label of the item or [None] for separators
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            MenuItem::Separator => None,
+            MenuItem::Button { name, .. } | MenuItem::Action { name, .. } | MenuItem::Menu { name, .. } => Some(name)
+        }
+    }
+
+    /// Converts the signal of this item, including the ones of all children
+    pub fn map<U, F>(self, mut f: F) -> MenuItem<U>
+    where
+        F: FnMut(T) -> U
+    {
+        self.map_with(&mut f)
+    }
+
+    fn map_with<U>(self, f: &mut impl FnMut(T) -> U) -> MenuItem<U> {
+        match self {
+            MenuItem::Separator => MenuItem::Separator,
+            MenuItem::Button { name, signal, checked } => MenuItem::Button {
+                name,
+                signal: f(signal),
+                checked
+            },
+            MenuItem::Action { name, action } => MenuItem::Action { name, action },
+            MenuItem::Menu { name, children } => MenuItem::Menu {
+                name,
+                children: children.into_iter().map(|item| item.map_with(f)).collect()
+            }
+        }
+    }
+}
+
+/// A shared closure that gets called when the associated [MenuItem::Action] is clicked
+///
+/// Two actions are only equal if they share the same closure.
+#[derive(Clone)]
+pub struct MenuAction(Arc<dyn Fn() + Send + Sync + 'static>);
+
+impl MenuAction {
+    pub fn new<F: Fn() + Send + Sync + 'static>(action: F) -> Self {
+        Self(Arc::new(action))
+    }
+
+    /// Runs the closure
+    pub fn call(&self) {
+        (self.0)()
+    }
+}
+
+impl Debug for MenuAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("MenuAction").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for MenuAction {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for MenuAction {}
+
+/// What happens when a clickable menu entry gets activated
+#[derive(Debug, Clone)]
+pub(crate) enum MenuSignal<T> {
+    Signal(T),
+    Action(MenuAction)
+}
+
+impl<T> MenuSignal<T> {
+    /// Runs the action or hands the signal to `emit`
+    pub(crate) fn dispatch(self, emit: impl FnOnce(T)) {
+        match self {
+            MenuSignal::Signal(signal) => emit(signal),
+            MenuSignal::Action(action) => action.call()
+        }
+    }
 }
 
 /// An icon struct
@@ -193,21 +1004,39 @@ pub struct Icon(NativeIcon);
 
 impl Icon {
     /// Creates a new icon from raw RGBA data
+    ///
+    /// Fails with [TrayErrorKind::InvalidIcon] if `rgba` doesn't hold exactly `width * height * 4` bytes.
     pub fn from_rgba(rgba: Vec<u8>, width: u32, height: u32) -> TrayResult<Self> {
-        ensure!(rgba.len() as u32 == width * height * 4, TrayError::custom("Invalid dimensions"));
-        Ok(Icon(NativeIcon::from_rgba(rgba, width, height)?))
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .ok_or_else(|| TrayError::new(TrayErrorKind::InvalidIcon, format!("Icon dimensions {width}x{height} are too large")))?;
+        ensure!(
+            rgba.len() == expected,
+            TrayError::new(
+                TrayErrorKind::InvalidIcon,
+                format!("Expected {expected} bytes of RGBA data for a {width}x{height} icon, got {}", rgba.len())
+            )
+        );
+        Ok(Icon(
+            NativeIcon::from_rgba(rgba, width, height).map_err(|err| err.with_kind(TrayErrorKind::InvalidIcon))?
+        ))
     }
 
     /// Creates a new icon from png encoded image data
     #[cfg(target_os = "linux")]
     pub fn from_png_bytes(bytes: &[u8]) -> TrayResult<Self> {
-        Ok(Icon(NativeIcon::from_png_bytes(bytes)?))
+        Ok(Icon(
+            NativeIcon::from_png_bytes(bytes).map_err(|err| err.with_kind(TrayErrorKind::InvalidIcon))?
+        ))
     }
 
     /// Creates a new icon from an embedded resource
     #[cfg(target_os = "windows")]
     pub fn from_resource(resource_id: u16, size: Option<(u32, u32)>) -> TrayResult<Self> {
-        Ok(Icon(NativeIcon::from_resource(resource_id, size)?))
+        Ok(Icon(
+            NativeIcon::from_resource(resource_id, size).map_err(|err| err.with_kind(TrayErrorKind::InvalidIcon))?
+        ))
     }
 }
 
@@ -216,3 +1045,164 @@ impl From<Icon> for NativeIcon {
         value.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels<T>(menu: &Menu<T>) -> Vec<Option<&str>> {
+        menu.items().iter().map(MenuItem::name).collect()
+    }
+
+    #[test]
+    fn merge_separates_non_empty_sections() {
+        let menu = Menu::merge([
+            Menu::empty(),
+            Menu::new([MenuItem::button("a", 1), MenuItem::button("b", 2)]),
+            Menu::empty(),
+            Menu::new([MenuItem::button("c", 3)]),
+            Menu::new([MenuItem::button("d", 4)]),
+            Menu::empty()
+        ]);
+        assert_eq!(labels(&menu), [Some("a"), Some("b"), None, Some("c"), None, Some("d")]);
+        assert!(Menu::<u32>::merge([Menu::empty(), Menu::empty()]).is_empty());
+        assert_eq!(labels(&Menu::merge([Menu::new([MenuItem::button("a", 1)])])), [Some("a")]);
+    }
+
+    #[test]
+    fn map_converts_nested_signals() {
+        let menu = Menu::new([
+            MenuItem::button("a", 1),
+            MenuItem::action("action", || {}),
+            MenuItem::menu("sub", [MenuItem::check_button("b", 2, true), MenuItem::separator()])
+        ])
+        .map(|signal| signal * 10);
+        assert_eq!(menu.items()[0], MenuItem::button("a", 10));
+        assert!(matches!(menu.items()[1], MenuItem::Action { .. }));
+        assert_eq!(
+            menu.items()[2],
+            MenuItem::menu("sub", [MenuItem::check_button("b", 20, true), MenuItem::separator()])
+        );
+    }
+
+    #[test]
+    fn find_searches_depth_first() {
+        let menu = Menu::new([
+            MenuItem::menu("outer", [MenuItem::menu("inner", [MenuItem::button("x", 1)])]),
+            MenuItem::button("x", 2)
+        ]);
+        assert_eq!(menu.find(|item| item.name() == Some("x")), Some(&MenuItem::button("x", 1)));
+        assert_eq!(
+            menu.find(|item| item.name() == Some("inner"))
+                .and_then(MenuItem::name),
+            Some("inner")
+        );
+        assert_eq!(menu.find(|item| item.name() == Some("missing")), None);
+    }
+
+    #[test]
+    fn find_mut_modifies_nested_items() {
+        let mut menu = Menu::new([
+            MenuItem::button("top", 0),
+            MenuItem::menu("outer", [MenuItem::menu("inner", [MenuItem::check_button("deep", 1, false)])])
+        ]);
+        match menu.find_mut(|item| item.name() == Some("deep")) {
+            Some(MenuItem::Button { checked, .. }) => *checked = Some(true),
+            other => panic!("unexpected item {other:?}")
+        }
+        let expected = MenuItem::menu("outer", [MenuItem::menu("inner", [MenuItem::check_button("deep", 1, true)])]);
+        assert_eq!(menu.items()[1], expected);
+        assert!(menu
+            .find_mut(|item| item.name() == Some("missing"))
+            .is_none());
+    }
+
+    #[test]
+    fn push_insert_and_extend() {
+        let mut menu: Menu<u32> = [MenuItem::button("b", 2)].into_iter().collect();
+        menu.push(MenuItem::button("d", 4));
+        menu.insert(0, MenuItem::button("a", 1));
+        menu.insert(2, MenuItem::button("c", 3));
+        menu.extend([MenuItem::separator(), MenuItem::button("e", 5)]);
+        assert_eq!(labels(&menu), [Some("a"), Some("b"), Some("c"), Some("d"), None, Some("e")]);
+        assert_eq!(menu.len(), 6);
+        assert_eq!(
+            menu.into_iter()
+                .filter_map(|item| item.name().map(str::to_owned))
+                .count(),
+            5
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn builder_from_toml_and_json() {
+        let expected = TrayIconBuilder::new()
+            .with_tooltip("Demo")
+            .with_menu(Menu::new([MenuItem::button("Quit", String::from("quit"))]))
+            .with_auto_toggle(true)
+            .with_handler_mode(HandlerMode::Concurrent)
+            .with_icon_cache_size(2);
+        let toml = r#"
+            tooltip = "Demo"
+            auto_toggle = true
+            handler_mode = "concurrent"
+            icon_cache_size = 2
+            menu = [{ type = "button", name = "Quit", signal = "quit" }]
+        "#;
+        assert_eq!(toml::from_str::<TrayIconBuilder<String>>(toml).unwrap(), expected);
+        let json = r#"{
+            "tooltip": "Demo",
+            "auto_toggle": true,
+            "handler_mode": "concurrent",
+            "icon_cache_size": 2,
+            "menu": [{ "type": "button", "name": "Quit", "signal": "quit" }]
+        }"#;
+        assert_eq!(serde_json::from_str::<TrayIconBuilder<String>>(json).unwrap(), expected);
+        assert_eq!(serde_json::from_str::<TrayIconBuilder<String>>("{}").unwrap(), TrayIconBuilder::new());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn snapshot_round_trip_with_action() {
+        let menu = Menu::new([
+            MenuItem::check_button("Enabled", 1, true),
+            MenuItem::separator(),
+            MenuItem::menu("More", [MenuItem::action("Open", || {}), MenuItem::button("Quit", 2)])
+        ]);
+        let snapshot = TraySnapshot {
+            tooltip: Some(String::from("Demo")),
+            icon: Some(SnapshotIcon::Image {
+                hash: 42,
+                size: Some((32, 32))
+            }),
+            menu: Some(menu.into_iter().map(SnapshotItem::from).collect()),
+            status: TrayStatus::Visible
+        };
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["menu"][2]["children"][0], serde_json::json!({ "type": "action", "name": "Open" }));
+        assert_eq!(serde_json::from_value::<TraySnapshot<u32>>(json).unwrap(), snapshot);
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "async-io"))]
+    fn bounded_channel_drops_events_when_full() {
+        use crate::platform::tests::{click, serve};
+
+        let (sender, receiver) = flume::bounded(2);
+        let menu = Menu::new([MenuItem::button("a", 1), MenuItem::button("b", 2), MenuItem::button("c", 3)]);
+        let (_tray, peer) = serve(TrayIconBuilder::new().with_menu(menu), forward_events(sender));
+        for id in [1, 2, 3] {
+            click(&peer, id);
+        }
+        assert_eq!(receiver.drain().collect::<Vec<_>>(), [TrayEvent::Menu(1), TrayEvent::Menu(2)]);
+        click(&peer, 3);
+        assert_eq!(receiver.try_recv(), Ok(TrayEvent::Menu(3)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_past_end_panics() {
+        Menu::new([MenuItem::button("a", 1)]).insert(2, MenuItem::separator());
+    }
+}
@@ -0,0 +1,339 @@
+/// Builds a [Menu](crate::Menu) from a tree of items
+///
+/// Items are separated by commas and mirror the constructors of [MenuItem](crate::MenuItem):
+/// `button(..)`, `check_button(..)`, `action(..)`, `separator` and `menu(name) { .. }` for submenus.
+/// Items can be generated with `for` loops and `if` / `else` conditions and existing menus can be spliced in with `..menu`.
+///
+/// ```
+/// # use betrayer::{menu, Menu};
+/// # #[derive(Clone)]
+/// # enum Signal { Profile(u32), Debug, Quit }
+/// # let selected = 0;
+/// # let debug = true;
+/// let menu: Menu<Signal> = menu![
+///     menu("Profiles") {
+///         for i in 0..5 {
+///             check_button(format!("Profile {}", i + 1), Signal::Profile(i), selected == i),
+///         }
+///     },
+///     separator,
+///     if debug {
+///         button("Debug", Signal::Debug),
+///     }
+///     action("About", || println!("betrayer")),
+///     button("Quit", Signal::Quit)
+/// ];
+/// ```
+#[macro_export]
+macro_rules! menu {
+    ($($tokens:tt)*) => {{
+        #[allow(unused_mut)]
+        let mut items = $crate::Menu::empty();
+        $crate::__menu_items!(items; $($tokens)*);
+        items
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __menu_items {
+    // for loops: collect the iterator expression until the body block
+    (@for $items:ident; [$pat:pat]; [$($iter:tt)+]; { $($body:tt)* } $($rest:tt)*) => {
+        for $pat in $($iter)+ {
+            $crate::__menu_items!($items; $($body)*);
+        }
+        $crate::__menu_items!(@block_end $items; $($rest)*);
+    };
+    (@for $items:ident; [$pat:pat]; [$($iter:tt)*]; $next:tt $($rest:tt)*) => {
+        $crate::__menu_items!(@for $items; [$pat]; [$($iter)* $next]; $($rest)*);
+    };
+    (@for $items:ident; [$pat:pat]; [$($iter:tt)*];) => {
+        $crate::__menu_expected_block!()
+    };
+
+    // if chains: collect the condition until the body block and build up the chain in front of it
+    (@if $items:ident; [$($chain:tt)*]; [$($cond:tt)+]; { $($body:tt)* } else if $($rest:tt)*) => {
+        $crate::__menu_items!(@if $items; [$($chain)* if $($cond)+ { $crate::__menu_items!($items; $($body)*); } else]; []; $($rest)*);
+    };
+    (@if $items:ident; [$($chain:tt)*]; [$($cond:tt)+]; { $($body:tt)* } else { $($otherwise:tt)* } $($rest:tt)*) => {
+        $($chain)* if $($cond)+ {
+            $crate::__menu_items!($items; $($body)*);
+        } else {
+            $crate::__menu_items!($items; $($otherwise)*);
+        }
+        $crate::__menu_items!(@block_end $items; $($rest)*);
+    };
+    (@if $items:ident; [$($chain:tt)*]; [$($cond:tt)+]; { $($body:tt)* } $($rest:tt)*) => {
+        $($chain)* if $($cond)+ {
+            $crate::__menu_items!($items; $($body)*);
+        }
+        $crate::__menu_items!(@block_end $items; $($rest)*);
+    };
+    (@if $items:ident; [$($chain:tt)*]; [$($cond:tt)*]; $next:tt $($rest:tt)*) => {
+        $crate::__menu_items!(@if $items; [$($chain)*]; [$($cond)* $next]; $($rest)*);
+    };
+    (@if $items:ident; [$($chain:tt)*]; [$($cond:tt)*];) => {
+        $crate::__menu_expected_block!()
+    };
+
+    // spliced menus: collect the expression until the next comma
+    (@splice $items:ident; [$($menu:tt)+]; , $($rest:tt)*) => {
+        $items.extend($($menu)+);
+        $crate::__menu_items!($items; $($rest)*);
+    };
+    (@splice $items:ident; [$($menu:tt)+];) => {
+        $items.extend($($menu)+);
+    };
+    (@splice $items:ident; [$($menu:tt)*]; $next:tt $($rest:tt)*) => {
+        $crate::__menu_items!(@splice $items; [$($menu)* $next]; $($rest)*);
+    };
+
+    // blocks can optionally be followed by a comma
+    (@block_end $items:ident; , $($rest:tt)*) => {
+        $crate::__menu_items!($items; $($rest)*);
+    };
+    (@block_end $items:ident; $($rest:tt)*) => {
+        $crate::__menu_items!($items; $($rest)*);
+    };
+
+    // everything else has to be separated by a comma
+    (@item_end $items:ident;) => {};
+    (@item_end $items:ident; , $($rest:tt)*) => {
+        $crate::__menu_items!($items; $($rest)*);
+    };
+    (@item_end $items:ident; $unexpected:tt $($rest:tt)*) => {
+        $crate::__menu_expected_comma!($unexpected)
+    };
+
+    ($items:ident;) => {};
+    ($items:ident; separator $($rest:tt)*) => {
+        $items.push($crate::MenuItem::separator());
+        $crate::__menu_items!(@item_end $items; $($rest)*);
+    };
+    ($items:ident; button($($args:tt)*) $($rest:tt)*) => {
+        $items.push($crate::MenuItem::button($($args)*));
+        $crate::__menu_items!(@item_end $items; $($rest)*);
+    };
+    ($items:ident; check_button($($args:tt)*) $($rest:tt)*) => {
+        $items.push($crate::MenuItem::check_button($($args)*));
+        $crate::__menu_items!(@item_end $items; $($rest)*);
+    };
+    ($items:ident; action($($args:tt)*) $($rest:tt)*) => {
+        $items.push($crate::MenuItem::action($($args)*));
+        $crate::__menu_items!(@item_end $items; $($rest)*);
+    };
+    ($items:ident; menu($($name:tt)*) { $($children:tt)* } $($rest:tt)*) => {
+        $items.push($crate::MenuItem::menu($($name)*, $crate::menu!($($children)*)));
+        $crate::__menu_items!(@block_end $items; $($rest)*);
+    };
+    ($items:ident; .. $($rest:tt)*) => {
+        $crate::__menu_items!(@splice $items; []; $($rest)*);
+    };
+    ($items:ident; for $pat:pat in $($rest:tt)*) => {
+        $crate::__menu_items!(@for $items; [$pat]; []; $($rest)*);
+    };
+    ($items:ident; if $($rest:tt)*) => {
+        $crate::__menu_items!(@if $items; []; []; $($rest)*);
+    };
+
+    // malformed items
+    ($items:ident; menu($($name:tt)*) $($unexpected:tt)*) => {
+        $crate::__menu_expected_block!($($unexpected)*)
+    };
+    ($items:ident; button $($unexpected:tt)*) => {
+        $crate::__menu_expected_arguments!($($unexpected)*)
+    };
+    ($items:ident; check_button $($unexpected:tt)*) => {
+        $crate::__menu_expected_arguments!($($unexpected)*)
+    };
+    ($items:ident; action $($unexpected:tt)*) => {
+        $crate::__menu_expected_arguments!($($unexpected)*)
+    };
+    ($items:ident; menu $($unexpected:tt)*) => {
+        $crate::__menu_expected_arguments!($($unexpected)*)
+    };
+    ($items:ident; for $($unexpected:tt)*) => {
+        $crate::__menu_expected_loop!($($unexpected)*)
+    };
+    ($items:ident; $unexpected:tt $($rest:tt)*) => {
+        $crate::__menu_expected_item!($unexpected)
+    };
+}
+
+// The following macros are only invoked with input they can't match. They exist so that the compiler points at the
+// offending token and shows what it expected instead.
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __menu_expected_comma {
+    (,) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __menu_expected_block {
+    ({}) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __menu_expected_arguments {
+    (($($args:tt)*)) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __menu_expected_loop {
+    ($pat:pat in $($iter:tt)*) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __menu_expected_item {
+    (separator) => {};
+    (button) => {};
+    (check_button) => {};
+    (action) => {};
+    (menu) => {};
+    (for) => {};
+    (if) => {};
+}
+
+/// Inputs the [menu!] macro has to reject
+///
+/// ```compile_fail
+/// let menu: betrayer::Menu<u32> = betrayer::menu![button("a", 1) button("b", 2)];
+/// ```
+///
+/// ```compile_fail
+/// let menu: betrayer::Menu<u32> = betrayer::menu![menu("Sub"), button("a", 1)];
+/// ```
+///
+/// ```compile_fail
+/// let menu: betrayer::Menu<u32> = betrayer::menu![button, separator];
+/// ```
+///
+/// ```compile_fail
+/// let menu: betrayer::Menu<u32> = betrayer::menu![for i in 0..3];
+/// ```
+///
+/// ```compile_fail
+/// let menu: betrayer::Menu<u32> = betrayer::menu![if true];
+/// ```
+///
+/// ```compile_fail
+/// let menu: betrayer::Menu<u32> = betrayer::menu![label("a", 1)];
+/// ```
+#[cfg(doctest)]
+struct MalformedMenus;
+
+#[cfg(test)]
+mod tests {
+    use crate::{Menu, MenuItem};
+
+    #[test]
+    fn items_and_submenus() {
+        let menu: Menu<u32> = menu![
+            button("a", 1),
+            check_button("b", 2, true),
+            separator,
+            menu("sub") {
+                button("c", 3),
+                menu("nested") {}
+            },
+            button("d", 4)
+        ];
+        let expected = Menu::new([
+            MenuItem::button("a", 1),
+            MenuItem::check_button("b", 2, true),
+            MenuItem::separator(),
+            MenuItem::menu("sub", [MenuItem::button("c", 3), MenuItem::menu("nested", [])]),
+            MenuItem::button("d", 4)
+        ]);
+        assert_eq!(menu, expected);
+        assert_eq!(menu![], Menu::<u32>::empty());
+    }
+
+    #[test]
+    fn trailing_commas_are_optional() {
+        let menu: Menu<u32> = menu![button("a", 1), menu("sub") { button("b", 2), }, separator,];
+        assert_eq!(menu.len(), 3);
+        assert_eq!(menu.items()[1], MenuItem::menu("sub", [MenuItem::button("b", 2)]));
+    }
+
+    #[test]
+    fn for_loops() {
+        let menu: Menu<u32> = menu![
+            for i in 0..3 {
+                button(format!("item {i}"), i)
+            }
+            separator,
+            for (i, name) in ["x", "y"].into_iter().enumerate() {
+                check_button(name, i as u32, i == 1),
+            },
+        ];
+        let names: Vec<_> = menu.items().iter().map(MenuItem::name).collect();
+        assert_eq!(names, [Some("item 0"), Some("item 1"), Some("item 2"), None, Some("x"), Some("y")]);
+        assert_eq!(menu.items()[5], MenuItem::check_button("y", 1, true));
+    }
+
+    #[test]
+    fn if_chains() {
+        let build = |value: u32| -> Menu<u32> {
+            menu![
+                if value == 0 {
+                    button("zero", 0)
+                } else if value == 1 {
+                    button("one", 1),
+                    separator
+                } else if let 2 = value {
+                    button("two", 2)
+                } else {
+                    button("many", value)
+                }
+                if value > 0 {
+                    button("positive", value)
+                },
+                button("last", 99)
+            ]
+        };
+        let names = |menu: Menu<u32>| {
+            menu.into_iter()
+                .map(|item| item.name().map(str::to_owned))
+                .collect::<Vec<_>>()
+        };
+        let names = |value| {
+            names(build(value))
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(names(0), "zero last");
+        assert_eq!(names(1), "one positive last");
+        assert_eq!(build(1).items()[1], MenuItem::separator());
+        assert_eq!(names(2), "two positive last");
+        assert_eq!(names(7), "many positive last");
+    }
+
+    #[test]
+    fn splices_and_actions() {
+        let section: Menu<u32> = Menu::new([MenuItem::button("b", 2), MenuItem::button("c", 3)]);
+        let menu: Menu<u32> = menu![
+            button("a", 1),
+            ..section.clone(),
+            ..[MenuItem::separator()],
+            action("about", || {}),
+            menu("sub") { ..section.clone().map(|s| s * 10) }
+        ];
+        let names: Vec<_> = menu.items().iter().map(MenuItem::name).collect();
+        assert_eq!(names, [Some("a"), Some("b"), Some("c"), None, Some("about"), Some("sub")]);
+        assert!(matches!(menu.items()[4], MenuItem::Action { .. }));
+        assert_eq!(
+            menu.items()[5],
+            MenuItem::menu("sub", [MenuItem::button("b", 20), MenuItem::button("c", 30)])
+        );
+        let spliced_last: Menu<u32> = menu![button("a", 1), ..section];
+        assert_eq!(spliced_last.len(), 3);
+    }
+}
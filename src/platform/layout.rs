@@ -0,0 +1,60 @@
+use crate::{Menu, MenuItem, MenuSignal};
+
+/// The structure of a menu without its signals
+///
+/// The native menus number their clickable entries depth first and keep the signals in a list with these ids,
+/// so the layout and that list are enough to turn a native menu back into a [Menu].
+pub enum MenuLayout {
+    Separator,
+    Entry { name: String, id: usize },
+    Menu { name: String, children: Vec<MenuLayout> }
+}
+
+impl MenuLayout {
+    /// The layout of `menu`, with the ids the native menus assign to its entries
+    pub fn of<T>(menu: &Menu<T>) -> Vec<Self> {
+        fn walk<T>(items: &[MenuItem<T>], next_id: &mut usize) -> Vec<MenuLayout> {
+            items
+                .iter()
+                .map(|item| match item {
+                    MenuItem::Separator => MenuLayout::Separator,
+                    MenuItem::Button { name, .. } | MenuItem::Action { name, .. } => {
+                        let id = *next_id;
+                        *next_id += 1;
+                        MenuLayout::Entry { name: name.clone(), id }
+                    }
+                    MenuItem::Menu { name, children } => MenuLayout::Menu {
+                        name: name.clone(),
+                        children: walk(children, next_id)
+                    }
+                })
+                .collect()
+        }
+        walk(menu.items(), &mut 0)
+    }
+
+    /// Turns the layout back into menu items, `entry` returns the signal and check state of an id
+    pub fn rebuild<T, F>(layout: &[Self], entry: &F) -> Vec<MenuItem<T>>
+    where
+        F: Fn(usize) -> Option<(MenuSignal<T>, Option<bool>)>
+    {
+        layout
+            .iter()
+            .filter_map(|item| match item {
+                MenuLayout::Separator => Some(MenuItem::Separator),
+                MenuLayout::Entry { name, id } => entry(*id).map(|(signal, checked)| match signal {
+                    MenuSignal::Signal(signal) => MenuItem::Button {
+                        name: name.clone(),
+                        signal,
+                        checked
+                    },
+                    MenuSignal::Action(action) => MenuItem::Action { name: name.clone(), action }
+                }),
+                MenuLayout::Menu { name, children } => Some(MenuItem::Menu {
+                    name: name.clone(),
+                    children: Self::rebuild(children, entry)
+                })
+            })
+            .collect()
+    }
+}
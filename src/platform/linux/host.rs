@@ -0,0 +1,149 @@
+use std::pin::pin;
+
+use futures_util::{stream, StreamExt};
+use zbus::names::WellKnownName;
+use zbus::proxy::CacheProperties;
+use zbus::{proxy, Connection};
+
+use crate::error::{TrayError, TrayErrorKind, TrayResult};
+use crate::platform::linux::{block_on, BusEvent};
+use crate::Support;
+
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+
+#[proxy(interface = "org.kde.StatusNotifierWatcher", assume_defaults = true)]
+pub trait StatusNotifierWatcher {
+    fn register_status_notifier_host(&self, service: &str) -> zbus::Result<()>;
+
+    fn register_status_notifier_item(&self, service: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn status_notifier_host_registered(&self) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn status_notifier_host_unregistered(&self) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn status_notifier_item_registered(&self, arg_1: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn status_notifier_item_unregistered(&self, arg_1: &str) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn is_status_notifier_host_registered(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn protocol_version(&self) -> zbus::Result<i32>;
+
+    #[zbus(property)]
+    fn registered_status_notifier_items(&self) -> zbus::Result<Vec<String>>;
+}
+
+/// A proxy for the watcher that always asks for the current host state, as watchers don't announce property changes
+pub async fn watcher_proxy(connection: &Connection) -> zbus::Result<StatusNotifierWatcherProxy<'static>> {
+    StatusNotifierWatcherProxy::builder(connection)
+        .path("/StatusNotifierWatcher")?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+}
+
+/// Registers an item with the watcher and returns whether a host is there to show it
+///
+/// With `wait` a missing watcher is not an error, the item is then registered by [follow_watcher] once a watcher appears.
+pub async fn register_item(watcher: &StatusNotifierWatcherProxy<'_>, service: &str, wait: bool) -> TrayResult<bool> {
+    match watcher.register_status_notifier_item(service).await {
+        Err(zbus::Error::MethodError(name, _, _)) if name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown" => {
+            crate::ensure!(
+                wait,
+                TrayError::new(
+                    TrayErrorKind::NoHost,
+                    "No StatusNotifierWatcher is running, the desktop doesn't support tray icons"
+                )
+            );
+            log::debug!("Waiting for a StatusNotifierWatcher to register {service}");
+            Ok(false)
+        }
+        result => {
+            result?;
+            Ok(host_registered(watcher).await)
+        }
+    }
+}
+
+pub async fn host_registered(watcher: &StatusNotifierWatcherProxy<'_>) -> bool {
+    watcher
+        .is_status_notifier_host_registered()
+        .await
+        .unwrap_or(false)
+}
+
+enum WatcherChange {
+    Owner(bool),
+    Host
+}
+
+/// Registers all `services` again whenever a new watcher appears and reports when a host appears or goes away
+pub async fn follow_watcher<S, N>(watcher: StatusNotifierWatcherProxy<'static>, services: S, notify: N) -> zbus::Result<()>
+where
+    S: Fn() -> Vec<String>,
+    N: Fn(BusEvent)
+{
+    let owner = watcher
+        .inner()
+        .receive_owner_changed()
+        .await?
+        .map(|owner| WatcherChange::Owner(owner.is_some()));
+    let registered = watcher
+        .receive_status_notifier_host_registered()
+        .await?
+        .map(|_| WatcherChange::Host);
+    let unregistered = watcher
+        .receive_status_notifier_host_unregistered()
+        .await?
+        .map(|_| WatcherChange::Host);
+    let mut changes = pin!(stream::select(owner, stream::select(registered, unregistered)));
+
+    let mut available = host_registered(&watcher).await;
+    while let Some(change) = changes.next().await {
+        if let WatcherChange::Owner(true) = change {
+            for service in services() {
+                watcher
+                    .register_status_notifier_item(&service)
+                    .await
+                    .unwrap_or_else(|err| log::warn!("Failed to register {service} with the new watcher: {err}"));
+            }
+        }
+        let host = match change {
+            WatcherChange::Owner(false) => false,
+            _ => host_registered(&watcher).await
+        };
+        if host != available {
+            available = host;
+            notify(BusEvent::Host(host));
+        }
+    }
+    Ok(())
+}
+
+pub async fn probe_async() -> TrayResult<Support> {
+    let connection = Connection::session().await?;
+    let name = WellKnownName::from_static_str_unchecked(WATCHER_NAME);
+    if !zbus::fdo::DBusProxy::new(&connection)
+        .await?
+        .name_has_owner(name.into())
+        .await
+        .map_err(zbus::Error::from)?
+    {
+        return Ok(Support::NoWatcher);
+    }
+    let watcher = watcher_proxy(&connection).await?;
+    Ok(match watcher.is_status_notifier_host_registered().await? {
+        true => Support::Available,
+        false => Support::NoHost
+    })
+}
+
+pub fn probe() -> TrayResult<Support> {
+    block_on(probe_async(), "probe_async")
+}
@@ -0,0 +1,543 @@
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fmt::{Debug, Formatter};
+use std::fs::{DirBuilder, OpenOptions};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+use crate::error::{TrayError, TrayErrorKind, TrayResult};
+use crate::SnapshotIcon;
+
+/// Every directory icons have been written to by this process
+static ICON_DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum NativeIcon {
+    #[allow(dead_code)]
+    Path(String),
+    Pixels(Vec<u8>)
+}
+
+impl NativeIcon {
+    pub fn from_rgba(rgba: Vec<u8>, width: u32, height: u32) -> TrayResult<Self> {
+        let mut pixels = Vec::new();
+        let mut encoder = Encoder::new(&mut pixels, width, height);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        let encode = |encoder: Encoder<&mut Vec<u8>>| {
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&rgba)?;
+            writer.finish()
+        };
+        encode(encoder).map_err(|err| TrayError::new(TrayErrorKind::InvalidIcon, format!("Failed to encode icon: {err}")))?;
+        Ok(Self::Pixels(pixels))
+    }
+
+    pub fn from_png_bytes(bytes: &[u8]) -> TrayResult<Self> {
+        Ok(Self::Pixels(bytes.to_vec()))
+    }
+}
+
+/// How the icon is passed to the tray host
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub enum ItemIcon {
+    #[default]
+    None,
+    Name(String),
+    /// ARGB32 images in network byte order
+    Pixmap(Vec<(i32, i32, Vec<u8>)>),
+    /// An icon from the [IconCache], which keeps its file as long as the icon is in use
+    Cached(Arc<CachedIcon>)
+}
+
+impl ItemIcon {
+    /// How a cached icon is passed to the tray host, other icons are returned as they are
+    pub fn resolve(&self) -> &ItemIcon {
+        match self {
+            ItemIcon::Cached(cached) => &cached.icon,
+            icon => icon
+        }
+    }
+
+    /// Describes a published icon, images are identified by the hash of their png data
+    pub fn describe(&self) -> Option<SnapshotIcon> {
+        match self {
+            ItemIcon::None | ItemIcon::Pixmap(_) => None,
+            ItemIcon::Name(name) => Some(SnapshotIcon::Named { name: name.clone() }),
+            ItemIcon::Cached(cached) => Some(SnapshotIcon::Image {
+                hash: cached.hash,
+                size: png_size(&cached.png)
+            })
+        }
+    }
+}
+
+/// An icon that was written to disk or decoded once, identified by a hash of its png data
+pub struct CachedIcon {
+    hash: u64,
+    png: Vec<u8>,
+    /// Either the name of the icon file or the decoded pixels
+    icon: ItemIcon
+}
+
+impl Debug for CachedIcon {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedIcon")
+            .field("hash", &format_args!("{:016x}", self.hash))
+            .field("icon", &self.icon)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for CachedIcon {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.png == other.png
+    }
+}
+
+impl Eq for CachedIcon {}
+
+/// The icons a tray has used recently, so switching between a few icons doesn't write or decode them again
+///
+/// The least recently used icon is dropped, and its file deleted, once the cache is full. Icons that are still in use,
+/// because they are published or wait in an update, are skipped until they are replaced, so the host never
+/// sees a deleted file. Dropping the cache deletes all files, even of icons that are still referenced.
+pub struct IconCache {
+    dir: Option<PathBuf>,
+    id: (u32, u32),
+    capacity: usize,
+    entries: VecDeque<CacheEntry>
+}
+
+struct CacheEntry {
+    icon: Arc<CachedIcon>,
+    _file: Option<TmpFileRaiiHandle>
+}
+
+impl CacheEntry {
+    fn in_use(&self) -> bool {
+        Arc::strong_count(&self.icon) > 1
+    }
+}
+
+impl IconCache {
+    /// Icon files are written to `dir`, or the default directory when `dir` is `None`
+    pub fn new(dir: Option<PathBuf>, id: (u32, u32), capacity: usize) -> Self {
+        Self {
+            dir,
+            id,
+            capacity: capacity.max(1),
+            entries: VecDeque::new()
+        }
+    }
+
+    /// Returns how `icon` is passed to the tray host, writing it to disk only if it isn't cached yet
+    ///
+    /// The icon is handed over as pixels instead if the path of the file can't be passed over DBus.
+    pub fn get(&mut self, icon: &NativeIcon) -> TrayResult<ItemIcon> {
+        let png = match icon {
+            NativeIcon::Path(path) => return Ok(ItemIcon::Name(path.clone())),
+            NativeIcon::Pixels(png) => png
+        };
+        let hash = {
+            let mut hasher = DefaultHasher::new();
+            png.hash(&mut hasher);
+            hasher.finish()
+        };
+        if let Some(entry) = self
+            .entries
+            .iter()
+            .position(|entry| entry.icon.hash == hash && entry.icon.png == *png)
+            .and_then(|index| self.entries.remove(index))
+        {
+            let icon = ItemIcon::Cached(entry.icon.clone());
+            self.entries.push_front(entry);
+            return Ok(icon);
+        }
+
+        let (pid, id) = self.id;
+        let tmp_path = icon_dir(self.dir.as_deref())?.join(format!("icon-{pid}-{id}-{hash:016x}.png"));
+        let (icon, file) = match tmp_path.to_str() {
+            Some(name) => {
+                create_icon_file(&tmp_path, png).map_err(|err| TrayError::custom(format!("Failed to write icon to {tmp_path:?}: {err}")))?;
+                (ItemIcon::Name(name.to_owned()), Some(TmpFileRaiiHandle(tmp_path)))
+            }
+            None => {
+                log::debug!("{tmp_path:?} is not valid UTF-8, passing the icon as pixmap instead");
+                (ItemIcon::Pixmap(vec![decode_png(png)?]), None)
+            }
+        };
+        let icon = Arc::new(CachedIcon {
+            hash,
+            png: png.clone(),
+            icon
+        });
+        self.entries.push_front(CacheEntry {
+            icon: icon.clone(),
+            _file: file
+        });
+        self.evict();
+        Ok(ItemIcon::Cached(icon))
+    }
+
+    /// Drops the least recently used icons that are not in use until the cache fits its capacity again
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            match self.entries.iter().rposition(|entry| !entry.in_use()) {
+                Some(index) => drop(self.entries.remove(index)),
+                None => break
+            }
+        }
+    }
+}
+
+struct TmpFileRaiiHandle(PathBuf);
+
+impl Drop for TmpFileRaiiHandle {
+    fn drop(&mut self) {
+        let path = &self.0;
+        std::fs::remove_file(path).unwrap_or_else(|err| log::warn!("Failed to clean up icon file at {path:?}: {err}"));
+    }
+}
+
+/// Returns the directory for icon files, creating it and removing stale icons when it is used for the first time
+fn icon_dir(custom: Option<&Path>) -> TrayResult<PathBuf> {
+    let dir = match custom {
+        Some(dir) => dir.to_path_buf(),
+        None => default_icon_dir()
+    };
+    let mut dirs = ICON_DIRS.lock();
+    if !dirs.contains(&dir) {
+        // A custom directory that already exists may hold files of the application that only look like icons
+        let owned = custom.is_none() || !dir.exists();
+        prepare_dir(&dir, custom.is_none()).map_err(|err| TrayError::custom(format!("Can't use {dir:?} for icon files: {err}")))?;
+        log::trace!("Using {dir:?} as tmp dir for icons");
+        if owned {
+            sweep_stale_icons(&dir);
+        }
+        dirs.push(dir.clone());
+    }
+    Ok(dir)
+}
+
+/// The runtime directory is private to the user, a shared temp directory needs a directory per user instead
+fn default_icon_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime) => PathBuf::from(runtime).join("betrayer"),
+        None => std::env::temp_dir().join(format!("betrayer-{}", current_uid()))
+    }
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and can't fail
+    unsafe { libc::geteuid() }
+}
+
+/// Makes sure that other users can't place or swap files in `dir`
+///
+/// The default directory must not be a symlink and is made private if necessary,
+/// a custom directory may be reached through symlinks but must not be writable by others either.
+fn prepare_dir(dir: &Path, default: bool) -> std::io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    let metadata = match default {
+        true => std::fs::symlink_metadata(dir)?,
+        false => std::fs::metadata(dir)?
+    };
+    let error = |msg: &str| Err(std::io::Error::new(ErrorKind::PermissionDenied, msg));
+    if !metadata.is_dir() {
+        return error("not a directory");
+    }
+    if metadata.uid() != current_uid() {
+        return error("owned by another user");
+    }
+    if default && metadata.mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    } else if metadata.mode() & 0o022 != 0 {
+        return error("writable by other users");
+    }
+    Ok(())
+}
+
+/// Creates a new file that is only readable by the current user
+///
+/// A file that is left over from an earlier process with the same pid is replaced.
+fn create_icon_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let open = || {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+    };
+    let mut file = match open() {
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            std::fs::remove_file(path)?;
+            open()?
+        }
+        result => result?
+    };
+    file.write_all(contents)
+        .inspect_err(|_| drop(std::fs::remove_file(path)))
+}
+
+/// Reads the width and height from the header of a png image
+fn png_size(png: &[u8]) -> Option<(u32, u32)> {
+    let reader = Decoder::new(png).read_info().ok()?;
+    Some((reader.info().width, reader.info().height))
+}
+
+/// Converts a png to the ARGB32 format of the `IconPixmap` property
+fn decode_png(png: &[u8]) -> TrayResult<(i32, i32, Vec<u8>)> {
+    let invalid = |err: png::DecodingError| TrayError::new(TrayErrorKind::InvalidIcon, format!("Failed to decode icon: {err}"));
+    let mut decoder = Decoder::new(png);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(invalid)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(invalid)?;
+    let pixels = &buffer[..info.buffer_size()];
+    let argb: Vec<u8> = match info.color_type {
+        ColorType::Rgba => pixels
+            .chunks_exact(4)
+            .flat_map(|p| [p[3], p[0], p[1], p[2]])
+            .collect(),
+        ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [u8::MAX, p[0], p[1], p[2]])
+            .collect(),
+        ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[1], p[0], p[0], p[0]])
+            .collect(),
+        ColorType::Grayscale => pixels.iter().flat_map(|&p| [u8::MAX, p, p, p]).collect(),
+        ColorType::Indexed => return Err(TrayError::new(TrayErrorKind::InvalidIcon, "Failed to expand the palette of the icon"))
+    };
+    let size = |value: u32| i32::try_from(value).map_err(|_| TrayError::new(TrayErrorKind::InvalidIcon, "The icon is too large"));
+    Ok((size(info.width)?, size(info.height)?, argb))
+}
+
+/// The pid in the name of an icon file, which is `icon-{pid}-{id}-{hash:016x}.png`
+fn icon_pid(name: &OsStr) -> Option<u32> {
+    let name = name.to_str()?.strip_prefix("icon-")?.strip_suffix(".png")?;
+    let mut parts = name.split('-');
+    let (pid, id, hash) = (parts.next()?, parts.next()?, parts.next()?);
+    let is_number = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let valid = parts.next().is_none()
+        && is_number(id)
+        && id.parse::<u32>().is_ok()
+        && hash.len() == 16
+        && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+    match valid && is_number(pid) {
+        true => pid.parse().ok(),
+        false => None
+    }
+}
+
+/// Deletes the icon files in `dir` that match `filter`
+fn remove_icons(dir: &Path, filter: impl Fn(u32) -> bool) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if icon_pid(&entry.file_name()).is_some_and(&filter) {
+            let path = entry.path();
+            std::fs::remove_file(&path).unwrap_or_else(|err| log::warn!("Failed to clean up icon file at {path:?}: {err}"));
+        }
+    }
+}
+
+/// Deletes the icons that were left behind by processes that no longer exist, for example because they crashed
+fn sweep_stale_icons(dir: &Path) {
+    // Without procfs every process would look dead
+    if Path::new("/proc/self").exists() {
+        remove_icons(dir, |pid| !Path::new("/proc").join(pid.to_string()).exists());
+    }
+}
+
+/// Deletes all icons of the current process, for when it is about to be terminated
+pub fn remove_process_icons() {
+    let current = std::process::id();
+    for dir in ICON_DIRS.lock().iter() {
+        remove_icons(dir, |pid| pid == current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn pixel(value: u8) -> NativeIcon {
+        NativeIcon::from_rgba(vec![value; 4], 1, 1).unwrap()
+    }
+
+    fn file(icon: &ItemIcon) -> PathBuf {
+        match icon.resolve() {
+            ItemIcon::Name(name) => PathBuf::from(name),
+            other => panic!("unexpected icon {other:?}")
+        }
+    }
+
+    #[test]
+    fn known_icons_are_not_written_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = IconCache::new(Some(dir.path().to_path_buf()), (std::process::id(), 0), 2);
+        let first = cache.get(&pixel(1)).unwrap();
+        // Removed behind the back of the cache, so writing it again would bring it back
+        std::fs::remove_file(file(&first)).unwrap();
+
+        let second = cache.get(&pixel(1)).unwrap();
+        assert_eq!(first, second);
+        assert!(names(dir.path()).is_empty());
+        assert_eq!(first.describe(), second.describe());
+    }
+
+    #[test]
+    fn least_recently_used_icons_are_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = IconCache::new(Some(dir.path().to_path_buf()), (std::process::id(), 0), 2);
+        let mut get = |value| file(&cache.get(&pixel(value)).unwrap());
+        let (a, b, c) = (get(1), get(2), get(3));
+        assert!(!a.exists() && b.exists() && c.exists());
+
+        // Using b again makes c the oldest icon
+        assert_eq!(get(2), b);
+        let d = get(4);
+        assert!(b.exists() && !c.exists() && d.exists());
+    }
+
+    #[test]
+    fn icons_in_use_are_not_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = IconCache::new(Some(dir.path().to_path_buf()), (std::process::id(), 0), 1);
+        let published = cache.get(&pixel(1)).unwrap();
+        let pending = cache.get(&pixel(2)).unwrap();
+        assert!(file(&published).exists() && file(&pending).exists());
+
+        // Once the pending icon was replaced, it is the only one that can go
+        let replaced = file(&pending);
+        drop(pending);
+        let next = cache.get(&pixel(3)).unwrap();
+        assert!(file(&published).exists() && !replaced.exists() && file(&next).exists());
+
+        drop(cache);
+        assert!(!file(&published).exists());
+    }
+
+    fn mode(path: &Path) -> u32 {
+        std::fs::metadata(path).unwrap().mode() & 0o777
+    }
+
+    #[test]
+    fn default_dirs_and_icons_are_private() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("betrayer");
+        prepare_dir(&dir, true).unwrap();
+        assert_eq!(mode(&dir), 0o700);
+
+        // An existing default directory is made private again
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        prepare_dir(&dir, true).unwrap();
+        assert_eq!(mode(&dir), 0o700);
+
+        let file = dir.join("icon-1-0-0123456789abcdef.png");
+        create_icon_file(&file, b"png").unwrap();
+        assert_eq!(mode(&file), 0o600);
+        // A left over file is replaced with a private one
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644)).unwrap();
+        create_icon_file(&file, b"new").unwrap();
+        assert_eq!(mode(&file), 0o600);
+        assert_eq!(std::fs::read(&file).unwrap(), b"new");
+    }
+
+    #[test]
+    fn shared_custom_dirs_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        prepare_dir(dir.path(), false).unwrap();
+
+        for writable in [0o775, 0o757] {
+            std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(writable)).unwrap();
+            let err = prepare_dir(dir.path(), false).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied, "{writable:o}");
+            // Unlike the default directory, a custom one is left as it is
+            assert_eq!(mode(dir.path()), writable);
+        }
+    }
+
+    #[test]
+    fn default_dirs_must_not_be_symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("target");
+        std::fs::create_dir(&target).unwrap();
+        let link = root.path().join("betrayer");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(prepare_dir(&link, true).is_err());
+        prepare_dir(&link, false).unwrap();
+    }
+
+    #[test]
+    fn only_icon_names_have_a_pid() {
+        assert_eq!(icon_pid(OsStr::new("icon-12-3-0123456789abcdef.png")), Some(12));
+        for name in [
+            "icon-12-3-0123456789abcdef.png.bak",
+            "icon-12-3-0123456789ABCDEF.png",
+            "icon-12-3-0123456789abcde.png",
+            "icon-12-0123456789abcdef.png",
+            "icon-12-3-4-0123456789abcdef.png",
+            "icon-+12-3-0123456789abcdef.png",
+            "icon-12-x-0123456789abcdef.png",
+            "icon-12-notes.png",
+            "my-icon-12-3-0123456789abcdef.png"
+        ] {
+            assert_eq!(icon_pid(OsStr::new(name)), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn sweep_keeps_look_alikes_and_live_icons() {
+        let dir = tempfile::tempdir().unwrap();
+        let own = format!("icon-{}-0-0123456789abcdef.png", std::process::id());
+        // No process can have this pid, as it is above the limit of the kernel
+        let stale = "icon-4294967295-0-0123456789abcdef.png";
+        let look_alikes = ["icon-4294967295-0-0123456789abcdef.png.bak", "icon-4294967295-notes.png"];
+        for name in look_alikes.iter().copied().chain([own.as_str(), stale]) {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        sweep_stale_icons(dir.path());
+
+        let mut expected: Vec<String> = look_alikes
+            .iter()
+            .copied()
+            .chain([own.as_str()])
+            .map(String::from)
+            .collect();
+        expected.sort();
+        assert_eq!(names(dir.path()), expected);
+    }
+
+    #[test]
+    fn existing_custom_dirs_are_not_swept() {
+        let dir = tempfile::tempdir().unwrap();
+        let stale = "icon-4294967295-0-0123456789abcdef.png";
+        std::fs::write(dir.path().join(stale), b"").unwrap();
+
+        icon_dir(Some(dir.path())).unwrap();
+
+        assert_eq!(names(dir.path()), [stale]);
+    }
+}
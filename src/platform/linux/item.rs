@@ -1,42 +1,74 @@
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use parking_lot::Mutex;
 use zbus::interface;
 use zbus::object_server::SignalEmitter;
-use zbus::zvariant::{ObjectPath, OwnedObjectPath};
+use zbus::zvariant::OwnedObjectPath;
 
-use crate::platform::linux::{TrayCallback, MENU_PATH};
+use crate::platform::linux::icon::ItemIcon;
+use crate::platform::linux::TrayCallback;
 use crate::{ClickType, TrayEvent};
 
 pub struct StatusNotifierItem<T> {
+    menu_path: OwnedObjectPath,
     first_activate: AtomicBool,
-    tooltip: Mutex<String>,
-    icon: Mutex<String>,
+    state: Arc<ItemState>,
     callback: TrayCallback<T>
 }
 
+/// What the item currently publishes, shared with the tray so it can be read back
+#[derive(Default)]
+pub struct ItemState {
+    pub tooltip: Mutex<Option<String>>,
+    pub icon: Mutex<ItemIcon>,
+    pub hidden: AtomicBool
+}
+
 impl<T> StatusNotifierItem<T> {
-    pub fn new(icon: String, tooltip: String, callback: TrayCallback<T>) -> Self {
+    pub fn new(menu_path: OwnedObjectPath, state: Arc<ItemState>, callback: TrayCallback<T>) -> Self {
         Self {
+            menu_path,
             first_activate: AtomicBool::new(true),
-            tooltip: Mutex::new(tooltip),
-            icon: Mutex::new(icon),
+            state,
             callback
         }
     }
+
+    /// An item with the same state that can be served on a new connection
+    pub fn duplicate(&self) -> Self {
+        Self {
+            menu_path: self.menu_path.clone(),
+            first_activate: AtomicBool::new(self.first_activate.load(Ordering::SeqCst)),
+            state: self.state.clone(),
+            callback: self.callback.clone()
+        }
+    }
 }
 
 impl<T: Send + 'static> StatusNotifierItem<T> {
-    pub async fn update_tooltip(&self, tooltip: String, signal_context: &SignalEmitter<'_>) -> zbus::Result<()> {
-        *self.tooltip.lock() = tooltip;
-        Self::new_tool_tip(signal_context).await?;
-        Ok(())
+    /// Changes the tooltip, the host only picks it up after [StatusNotifierItem::tooltip_changed]
+    pub fn set_tooltip(&self, tooltip: Option<String>) {
+        *self.state.tooltip.lock() = tooltip;
+    }
+
+    pub async fn tooltip_changed(signal_context: &SignalEmitter<'_>) -> zbus::Result<()> {
+        Self::new_tool_tip(signal_context).await
     }
 
-    pub async fn update_icon(&self, icon: String, signal_context: &SignalEmitter<'_>) -> zbus::Result<()> {
-        *self.icon.lock() = icon;
-        Self::new_icon(signal_context).await?;
-        Ok(())
+    /// Asks the host to hide the item, hosts that read the status later on see it as hidden as well
+    pub async fn hide(&self, signal_context: &SignalEmitter<'_>) -> zbus::Result<()> {
+        self.state.hidden.store(true, Ordering::SeqCst);
+        Self::new_status(signal_context, &self.status()).await
+    }
+
+    /// Changes the icon, the host only picks it up after [StatusNotifierItem::icon_changed]
+    pub fn set_icon(&self, icon: ItemIcon) {
+        *self.state.icon.lock() = icon;
+    }
+
+    pub async fn icon_changed(signal_context: &SignalEmitter<'_>) -> zbus::Result<()> {
+        Self::new_icon(signal_context).await
     }
 }
 
@@ -103,12 +135,18 @@ impl<T: Send + 'static> StatusNotifierItem<T> {
 
     #[zbus(property)]
     fn icon_name(&self) -> String {
-        self.icon.lock().clone()
+        match self.state.icon.lock().resolve() {
+            ItemIcon::Name(name) => name.clone(),
+            _ => String::new()
+        }
     }
 
     #[zbus(property)]
     fn icon_pixmap(&self) -> Vec<(i32, i32, Vec<u8>)> {
-        Default::default()
+        match self.state.icon.lock().resolve() {
+            ItemIcon::Pixmap(pixmap) => pixmap.clone(),
+            _ => Vec::new()
+        }
     }
 
     #[zbus(property)]
@@ -129,7 +167,7 @@ impl<T: Send + 'static> StatusNotifierItem<T> {
 
     #[zbus(property)]
     fn menu(&self) -> OwnedObjectPath {
-        ObjectPath::from_str_unchecked(MENU_PATH).into()
+        self.menu_path.clone()
     }
 
     #[zbus(property)]
@@ -144,7 +182,10 @@ impl<T: Send + 'static> StatusNotifierItem<T> {
 
     #[zbus(property)]
     fn status(&self) -> String {
-        String::from("Active")
+        match self.state.hidden.load(Ordering::SeqCst) {
+            true => String::from("Passive"),
+            false => String::from("Active")
+        }
     }
 
     #[zbus(property)]
@@ -155,7 +196,12 @@ impl<T: Send + 'static> StatusNotifierItem<T> {
     #[allow(clippy::type_complexity)]
     #[zbus(property)]
     fn tool_tip(&self) -> (String, Vec<(i32, i32, Vec<u8>)>, String, String) {
-        (String::new(), Vec::new(), self.tooltip.lock().clone(), String::new())
+        (
+            String::new(),
+            Vec::new(),
+            self.state.tooltip.lock().clone().unwrap_or_default(),
+            String::new()
+        )
     }
 
     #[zbus(property)]
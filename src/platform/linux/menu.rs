@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::mem::swap;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
 
 use parking_lot::Mutex;
 use zbus::interface;
@@ -8,13 +9,49 @@ use zbus::object_server::SignalEmitter;
 use zbus::zvariant::{OwnedValue, Str, Value};
 
 use crate::platform::linux::TrayCallback;
-use crate::{ClickType, Menu, MenuItem, TrayEvent};
+use crate::{ClickType, Menu, MenuItem, MenuSignal, TrayEvent};
 
-//#[derive(Clone)]
 struct MenuEntry<T> {
     properties: HashMap<String, OwnedValue>,
     children: Vec<usize>,
-    signal: Option<T>
+    signal: Option<MenuSignal<T>>
+}
+
+impl<T: Clone> MenuEntry<T> {
+    /// Turns the entry back into the item it was built from, with the current check state
+    fn to_item(&self, entries: &[MenuEntry<T>]) -> MenuItem<T> {
+        let name = match self.properties.get("label").map(|v| &**v) {
+            Some(Value::Str(label)) => label.to_string(),
+            _ => String::new()
+        };
+        match &self.signal {
+            Some(MenuSignal::Signal(signal)) => MenuItem::Button {
+                name,
+                signal: signal.clone(),
+                checked: self
+                    .properties
+                    .get("toggle-state")
+                    .and_then(|v| i32::try_from(v).ok())
+                    .map(|state| state != 0)
+            },
+            Some(MenuSignal::Action(action)) => MenuItem::Action {
+                name,
+                action: action.clone()
+            },
+            None if self.properties.contains_key("type") => MenuItem::Separator,
+            None => MenuItem::Menu {
+                name,
+                children: rebuild(&self.children, entries)
+            }
+        }
+    }
+}
+
+fn rebuild<T: Clone>(ids: &[usize], entries: &[MenuEntry<T>]) -> Vec<MenuItem<T>> {
+    ids.iter()
+        .filter_map(|&id| entries.get(id))
+        .map(|entry| entry.to_item(entries))
+        .collect()
 }
 
 impl<T> MenuEntry<T> {
@@ -25,30 +62,98 @@ impl<T> MenuEntry<T> {
             .map(clone_tuple)
             .collect()
     }
+
+    fn toggle(&mut self) -> Option<bool> {
+        let checked = self
+            .properties
+            .get("toggle-state")
+            .and_then(|v| i32::try_from(v).ok())?
+            == 0;
+        self.properties
+            .insert(String::from("toggle-state"), OwnedValue::from(i32::from(checked)));
+        Some(checked)
+    }
+}
+
+pub struct MenuState<T> {
+    entries: Arc<Mutex<Vec<MenuEntry<T>>>>,
+    is_set: Arc<AtomicBool>
+}
+
+impl<T: Clone> MenuState<T> {
+    /// The menu as it is currently shown, including check states that were flipped by auto toggle
+    ///
+    /// `None` if the tray was given no menu, even though an empty menu is still served.
+    pub fn menu(&self) -> Option<Menu<T>> {
+        let entries = self.entries.lock();
+        if !self.is_set.load(Ordering::SeqCst) {
+            return None;
+        }
+        let items = entries
+            .first()
+            .map(|root| rebuild(&root.children, &entries))
+            .unwrap_or_default();
+        Some(Menu::new(items))
+    }
+}
+
+impl<T> Clone for MenuState<T> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            is_set: self.is_set.clone()
+        }
+    }
 }
 
 pub struct DBusMenu<T> {
     revision: AtomicU32,
-    entries: Mutex<Vec<MenuEntry<T>>>,
+    entries: Arc<Mutex<Vec<MenuEntry<T>>>>,
+    is_set: Arc<AtomicBool>,
+    auto_toggle: bool,
     callback: TrayCallback<T>
 }
 
 impl<T> DBusMenu<T> {
-    pub fn new(menu: Menu<T>, callback: TrayCallback<T>) -> Self {
-        let entries = build_menu(menu);
+    pub fn new(menu: Option<Menu<T>>, auto_toggle: bool, callback: TrayCallback<T>) -> Self {
+        let is_set = Arc::new(AtomicBool::new(menu.is_some()));
+        let entries = build_menu(menu.unwrap_or_else(Menu::empty));
         Self {
             revision: AtomicU32::new(0),
-            entries: Mutex::new(entries),
+            entries: Arc::new(Mutex::new(entries)),
+            is_set,
+            auto_toggle,
             callback
         }
     }
+
+    /// The entries of the menu, shared with the tray so they can be read back
+    pub fn state(&self) -> MenuState<T> {
+        MenuState {
+            entries: self.entries.clone(),
+            is_set: self.is_set.clone()
+        }
+    }
+
+    /// A menu with the same state that can be served on a new connection
+    pub fn duplicate(&self) -> Self {
+        Self {
+            revision: AtomicU32::new(self.revision.load(Ordering::SeqCst)),
+            entries: self.entries.clone(),
+            is_set: self.is_set.clone(),
+            auto_toggle: self.auto_toggle,
+            callback: self.callback.clone()
+        }
+    }
 }
 
 impl<T: Clone + Send + 'static> DBusMenu<T> {
-    pub async fn update_menu(&self, menu: Menu<T>, signal_context: &SignalEmitter<'_>) -> zbus::Result<()> {
+    /// Replaces the menu, `None` is served as an empty menu
+    pub async fn update_menu(&self, menu: Option<Menu<T>>, signal_context: &SignalEmitter<'_>) -> zbus::Result<()> {
         let (layout, updated, removed) = {
             let mut current_entries = self.entries.lock();
-            let mut entries = build_menu(menu);
+            self.is_set.store(menu.is_some(), Ordering::SeqCst);
+            let mut entries = build_menu(menu.unwrap_or_else(Menu::empty));
             swap(&mut entries, &mut current_entries);
             generate_diff(&current_entries, &entries)
         };
@@ -63,6 +168,33 @@ impl<T: Clone + Send + 'static> DBusMenu<T> {
         }
         Ok(())
     }
+
+    async fn clicked(&self, id: i32, signal_context: &SignalEmitter<'_>) -> zbus::Result<()> {
+        let (signal, toggled) = {
+            let mut entries = self.entries.lock();
+            let Some(entry) = entries.get_mut(id as usize) else {
+                return Ok(());
+            };
+            let toggled = match self.auto_toggle {
+                true => entry.toggle(),
+                false => None
+            };
+            (entry.signal.clone(), toggled)
+        };
+        let Some(signal) = signal else {
+            return Ok(());
+        };
+        match toggled {
+            Some(checked) => {
+                let updated = [(id, HashMap::from([(String::from("toggle-state"), OwnedValue::from(i32::from(checked)))]))];
+                log::trace!("Sending property update signal (Updated: {updated:?})");
+                Self::items_properties_updated(signal_context, &updated, &[]).await?;
+                signal.dispatch(|signal| (self.callback.lock())(TrayEvent::MenuToggled(signal, checked)));
+            }
+            None => signal.dispatch(|signal| (self.callback.lock())(TrayEvent::Menu(signal)))
+        }
+        Ok(())
+    }
 }
 
 fn build_menu<T>(menu: Menu<T>) -> Vec<MenuEntry<T>> {
@@ -97,9 +229,14 @@ fn build_menu<T>(menu: Menu<T>) -> Vec<MenuEntry<T>> {
                 MenuEntry {
                     properties: props,
                     children: vec![],
-                    signal: Some(signal)
+                    signal: Some(MenuSignal::Signal(signal))
                 }
             }
+            MenuItem::Action { name, action } => MenuEntry {
+                properties: HashMap::from([(String::from("label"), OwnedValue::from(Str::from(name)))]),
+                children: vec![],
+                signal: Some(MenuSignal::Action(action))
+            },
             MenuItem::Menu { name, children } => MenuEntry {
                 properties: HashMap::from([
                     (String::from("label"), OwnedValue::from(Str::from(name))),
@@ -243,32 +380,29 @@ impl<T: Clone + Send + 'static> DBusMenu<T> {
             .unwrap_or(OwnedValue::from(Str::from_static("")))
     }
 
-    fn event(&self, id: i32, event_id: &str, data: Value<'_>, timestamp: u32) {
+    async fn event(
+        &self, id: i32, event_id: &str, data: Value<'_>, timestamp: u32, #[zbus(signal_emitter)] signal_context: SignalEmitter<'_>
+    ) -> zbus::fdo::Result<()> {
         log::trace!("event({}, {}, {:?}, {})", id, event_id, data, timestamp);
         match event_id {
-            "clicked" => {
-                let signal = self
-                    .entries
-                    .lock()
-                    .get(id as usize)
-                    .and_then(|e| e.signal.clone());
-                if let Some(signal) = signal {
-                    (self.callback.lock())(TrayEvent::Menu(signal));
-                }
-            }
+            "clicked" => self.clicked(id, &signal_context).await?,
             "opened" if id == 0 => {
                 (self.callback.lock())(TrayEvent::Tray(ClickType::Left));
             }
             _ => {}
         }
+        Ok(())
     }
 
-    fn event_group(&self, events: Vec<(i32, &str, Value<'_>, u32)>) -> Vec<i32> {
+    async fn event_group(
+        &self, events: Vec<(i32, &str, Value<'_>, u32)>, #[zbus(signal_emitter)] signal_context: SignalEmitter<'_>
+    ) -> zbus::fdo::Result<Vec<i32>> {
         for (id, event, data, timestamp) in events {
-            self.event(id, event, data, timestamp);
+            self.event(id, event, data, timestamp, signal_context.clone())
+                .await?;
         }
         //TODO return list of failed ids
-        Vec::new()
+        Ok(Vec::new())
     }
 
     fn about_to_show(&self, _id: i32) -> bool {
@@ -315,3 +449,56 @@ impl<T: Clone + Send + 'static> DBusMenu<T> {
 fn clone_tuple((a, b): (&String, &OwnedValue)) -> (String, OwnedValue) {
     (a.clone(), b.try_clone().expect("failed to clone"))
 }
+
+#[cfg(all(test, feature = "async-io"))]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    use zbus::MessageStream;
+
+    use super::*;
+    use crate::platform::linux::tests::{click, next_signal, serve};
+    use crate::TrayIconBuilder;
+
+    type PropertiesUpdated = (Vec<(i32, HashMap<String, OwnedValue>)>, Vec<(i32, Vec<String>)>);
+
+    #[test]
+    fn auto_toggle_flips_check_buttons() {
+        let (sender, events) = flume::unbounded();
+        let builder = TrayIconBuilder::new()
+            .with_auto_toggle(true)
+            .with_menu(Menu::new([MenuItem::check_button("a", 1, false)]));
+        let (tray, peer) = serve(builder, move |event| sender.send(event).unwrap());
+        let mut signals = MessageStream::from(&peer);
+        click(&peer, 1);
+
+        let signal = next_signal(&mut signals, "ItemsPropertiesUpdated");
+        let (updated, removed) = signal.body().deserialize::<PropertiesUpdated>().unwrap();
+        assert_eq!(updated, [(1, HashMap::from([(String::from("toggle-state"), OwnedValue::from(1i32))]))]);
+        assert!(removed.is_empty());
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)), Ok(TrayEvent::MenuToggled(1, true)));
+        assert_eq!(tray.menu(), Some(Menu::new([MenuItem::check_button("a", 1, true)])));
+    }
+
+    #[test]
+    fn actions_run_instead_of_emitting_events() {
+        let (sender, events) = flume::unbounded();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let action = {
+            let runs = runs.clone();
+            MenuItem::action("b", move || {
+                runs.fetch_add(1, Ordering::AcqRel);
+            })
+        };
+        let builder = TrayIconBuilder::new().with_menu(Menu::new([MenuItem::button("a", 1), action]));
+        let (_tray, peer) = serve(builder, move |event| sender.send(event).unwrap());
+
+        click(&peer, 2);
+        assert_eq!(runs.load(Ordering::Acquire), 1);
+        click(&peer, 1);
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)), Ok(TrayEvent::Menu(1)));
+        assert!(events.is_empty());
+        assert_eq!(runs.load(Ordering::Acquire), 1);
+    }
+}
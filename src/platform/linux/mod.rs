@@ -1,289 +1,1212 @@
+mod host;
+mod icon;
 mod item;
 mod menu;
+mod run;
+mod shared;
+mod signals;
+#[cfg(all(test, feature = "async-io"))]
+mod test_bus;
 
-use std::cell::Cell;
-use std::path::PathBuf;
+use std::future::Future;
+use std::pin::pin;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::{Arc, OnceLock};
+use std::sync::{Arc, Weak};
+use std::time::Instant;
 
-use flume::Sender;
-use futures_util::{StreamExt, TryStreamExt};
+use flume::{Receiver, Sender};
+use futures_util::future::{select, Either};
 use parking_lot::Mutex;
-use png::{BitDepth, ColorType, Encoder};
-use zbus::{connection, proxy, Task};
+use zbus::zvariant::{ObjectPath, OwnedObjectPath};
+use zbus::{connection, Connection, Executor, Task};
 
-use crate::error::{ErrorSource, TrayResult};
-use crate::platform::linux::item::StatusNotifierItem;
-use crate::platform::linux::menu::DBusMenu;
-use crate::{Icon, Menu, TrayEvent, TrayIconBuilder};
+use crate::error::{ErrorHook, ErrorSource, TrayError, TrayErrorKind, TrayResult};
+use crate::platform::linux::host::{follow_watcher, register_item, watcher_proxy};
+pub use crate::platform::linux::host::{probe, probe_async};
+pub use crate::platform::linux::icon::NativeIcon;
+use crate::platform::linux::icon::{IconCache, ItemIcon};
+use crate::platform::linux::item::{ItemState, StatusNotifierItem};
+use crate::platform::linux::menu::{DBusMenu, MenuState};
+pub use crate::platform::linux::run::run;
+use crate::platform::linux::shared::{ItemConnection, SharedConnection};
+use crate::{ConnectionState, Icon, Menu, SnapshotIcon, SnapshotItem, TrayChanges, TrayEvent, TrayIconBuilder, TraySnapshot, TrayStatus};
 
 static MENU_PATH: &str = "/MenuBar";
 static ITEM_PATH: &str = "/StatusNotifierItem";
 static COUNTER: AtomicU32 = AtomicU32::new(1);
 
-enum TrayUpdate<T> {
-    Menu(Menu<T>),
-    Tooltip(String),
-    Icon(String),
+/// The object paths of a tray item and its menu
+#[derive(Debug, Clone)]
+struct ItemPaths {
+    item: OwnedObjectPath,
+    menu: OwnedObjectPath
+}
+
+impl ItemPaths {
+    /// The well-known paths, used when the tray owns its connection
+    fn fixed() -> Self {
+        Self {
+            item: ObjectPath::from_static_str_unchecked(ITEM_PATH).into(),
+            menu: ObjectPath::from_static_str_unchecked(MENU_PATH).into()
+        }
+    }
+
+    /// Paths that don't collide with other trays or objects on a shared connection
+    fn unique(id: u32) -> Self {
+        Self {
+            item: ObjectPath::from_string_unchecked(format!("{ITEM_PATH}/{id}")).into(),
+            menu: ObjectPath::from_string_unchecked(format!("{MENU_PATH}/{id}")).into()
+        }
+    }
+}
+
+/// A set of changes, values that are `None` stay as they are
+struct TrayUpdate<T> {
+    tooltip: Option<Option<String>>,
+    icon: Option<ItemIcon>,
+    menu: Option<Option<Menu<T>>>
+}
+
+impl<T> Default for TrayUpdate<T> {
+    fn default() -> Self {
+        Self {
+            tooltip: None,
+            icon: None,
+            menu: None
+        }
+    }
+}
+
+impl<T> TrayUpdate<T> {
+    /// Adds the changes of a later update, which replace the changes of this one
+    fn merge(&mut self, newer: Self) {
+        self.tooltip = newer.tooltip.or(self.tooltip.take());
+        self.icon = newer.icon.or(self.icon.take());
+        self.menu = newer.menu.or(self.menu.take());
+    }
+}
+
+struct TrayUpdateRequest<T> {
+    update: TrayUpdate<T>,
+    done: Option<Sender<TrayResult<()>>>
+}
+
+async fn apply_update<T: Clone + Send + 'static>(connection: &Connection, paths: &ItemPaths, update: TrayUpdate<T>) -> zbus::Result<()> {
+    let object_server = connection.object_server();
+    let item = object_server
+        .interface::<_, StatusNotifierItem<T>>(&paths.item)
+        .await?;
+    // Everything is changed before the first signal, so a host that reloads the whole item already sees the final state
+    let (tooltip, icon) = {
+        let iref = item.get().await;
+        let tooltip = update.tooltip.map(|tooltip| iref.set_tooltip(tooltip));
+        let icon = update.icon.map(|icon| iref.set_icon(icon));
+        (tooltip.is_some(), icon.is_some())
+    };
+    if let Some(menu) = update.menu {
+        let iface = object_server
+            .interface::<_, DBusMenu<T>>(&paths.menu)
+            .await?;
+        let iref = iface.get().await;
+        iref.update_menu(menu, iface.signal_emitter()).await?;
+    }
+    if tooltip {
+        StatusNotifierItem::<T>::tooltip_changed(item.signal_emitter()).await?;
+    }
+    if icon {
+        StatusNotifierItem::<T>::icon_changed(item.signal_emitter()).await?;
+    }
+    Ok(())
+}
+
+/// Applies the requests of `receiver` until all senders are gone
+///
+/// Updates that piled up in the meantime are merged, so the host only gets the final state.
+/// Every waiting request gets the result, errors of requests that nobody waits for go to the hook.
+async fn receive_updates<T, F, R>(receiver: Receiver<TrayUpdateRequest<T>>, hook: Option<ErrorHook>, mut apply: F)
+where
+    F: FnMut(TrayUpdate<T>) -> R,
+    R: Future<Output = zbus::Result<()>>
+{
+    while let Ok(TrayUpdateRequest { mut update, done }) = receiver.recv_async().await {
+        let mut detached = done.is_none();
+        let mut waiting = Vec::from_iter(done);
+        for request in receiver.drain() {
+            update.merge(request.update);
+            detached |= request.done.is_none();
+            waiting.extend(request.done);
+        }
+        let result = apply(update).await;
+        for done in waiting {
+            let _ = done.send(result.clone().map_err(TrayError::from));
+        }
+        if let (Err(err), true) = (result, detached) {
+            ErrorHook::report(hook.as_ref(), err.into());
+        }
+    }
 }
 
 pub type TrayCallback<T> = Arc<Mutex<dyn FnMut(TrayEvent<T>) + Send + 'static>>;
 
+/// An event that concerns all trays of a connection
+#[derive(Debug, Copy, Clone)]
+enum BusEvent {
+    Connection(ConnectionState),
+    Host(bool)
+}
+
+/// The status of a tray, which changes with the events of its connection
+type SharedStatus = Arc<Mutex<TrayStatus>>;
+
+impl BusEvent {
+    /// Updates the status of a tray after this event
+    ///
+    /// A restored connection doesn't tell whether a host is there, the status is set when the tray is registered again.
+    fn update(self, status: &Mutex<TrayStatus>) {
+        match self {
+            BusEvent::Connection(ConnectionState::Disconnected) => *status.lock() = TrayStatus::Disconnected,
+            BusEvent::Connection(ConnectionState::Reconnected) => {}
+            BusEvent::Host(true) => *status.lock() = TrayStatus::Visible,
+            BusEvent::Host(false) => *status.lock() = TrayStatus::NoHost
+        }
+    }
+
+    fn into_event<T>(self) -> TrayEvent<T> {
+        match self {
+            BusEvent::Connection(state) => TrayEvent::Connection(state),
+            BusEvent::Host(true) => TrayEvent::HostAvailable,
+            BusEvent::Host(false) => TrayEvent::HostUnavailable
+        }
+    }
+}
+
+/// The connection a tray is served on
+#[derive(Clone)]
+enum Link {
+    /// A connection that stays the same for the lifetime of the tray
+    Fixed(Connection),
+    /// The connection of the tray on the executor shared by the whole process, which is replaced when it gets lost
+    Shared(Arc<SharedConnection>, Arc<ItemConnection>)
+}
+
+impl Link {
+    fn connection(&self) -> Connection {
+        match self {
+            Link::Fixed(connection) => connection.clone(),
+            Link::Shared(_, item) => item.connection()
+        }
+    }
+
+    fn executor(&self) -> &Executor<'static> {
+        match self {
+            Link::Fixed(connection) => connection.executor(),
+            Link::Shared(shared, _) => shared.executor()
+        }
+    }
+}
+
 pub struct NativeTrayIcon<T> {
-    id: (u32, u32),
-    sender: Sender<TrayUpdate<T>>,
-    tmp_icon_file: Cell<Option<TmpFileRaiiHandle>>,
-    tmp_icon_counter: Cell<u32>,
+    link: Link,
+    paths: ItemPaths,
+    handle: NativeTrayHandle<T>,
+    _icon_cache: Arc<Mutex<IconCache>>,
     _update_task: Task<()>,
-    _register_task: Task<Result<(), zbus::Error>>,
+    _register_task: Option<Task<Result<(), zbus::Error>>>,
+    _handler_task: Option<Task<()>>,
+    _cleanup: Option<ObjectCleanup>
 }
 
 impl<T: Clone + Send + 'static> NativeTrayIcon<T> {
     pub async fn new_async<F>(builder: TrayIconBuilder<T>, callback: F) -> TrayResult<Self>
     where
-        F: FnMut(TrayEvent<T>) + Send + 'static,
+        F: FnMut(TrayEvent<T>) + Send + 'static
+    {
+        let shared = SharedConnection::get().await?;
+        let id = COUNTER.fetch_add(1, Ordering::AcqRel);
+        let item = shared
+            .open_item(format!("org.kde.StatusNotifierItem-{}-{id}", std::process::id()))
+            .await?;
+        let paths = ItemPaths::fixed();
+        let wait = builder.wait_for_watcher;
+        let callback: TrayCallback<T> = Arc::new(Mutex::new(callback));
+        let link = Link::Shared(shared.clone(), item.clone());
+        let mut tray = Self::setup(link.clone(), id, paths.clone(), builder, callback.clone()).await?;
+        tray._cleanup = Some(ObjectCleanup::new::<T>(link, paths.clone()));
+        shared
+            .register(item, paths, wait, callback, tray.shared_status())
+            .await?;
+        Ok(tray)
+    }
+
+    /// Like [NativeTrayIcon::new], but the DBus connection only makes progress while [NativeTrayIcon::executor] is ticked
+    ///
+    /// Connections that are created within a tokio runtime hand their tasks to the runtime and ticking their executor never returns.
+    #[cfg(any(feature = "calloop", feature = "glib"))]
+    pub fn new_external<F>(builder: TrayIconBuilder<T>, callback: F) -> TrayResult<Self>
+    where
+        F: FnMut(TrayEvent<T>) + Send + 'static
     {
+        #[cfg(feature = "tokio")]
+        crate::ensure!(
+            tokio::runtime::Handle::try_current().is_err(),
+            TrayError::new(
+                TrayErrorKind::Unsupported,
+                "Trays that are driven by an external event loop can't be created within a tokio runtime"
+            )
+        );
+        block_on(
+            async {
+                let (conn, id, name) = Self::connect().await?;
+                drive(conn.executor(), async {
+                    let wait = builder.wait_for_watcher;
+                    let callback: TrayCallback<T> = Arc::new(Mutex::new(callback));
+                    let mut tray = Self::setup(Link::Fixed(conn.clone()), id, ItemPaths::fixed(), builder, callback.clone()).await?;
+                    tray.register_with_watcher(&conn, name, wait, callback)
+                        .await?;
+                    Ok(tray)
+                })
+                .await
+            },
+            "build_async"
+        )
+    }
+
+    /// Opens a new connection that owns the well-known name of a tray and has to be driven by the caller
+    async fn connect() -> TrayResult<(Connection, u32, String)> {
         let pid = std::process::id();
         let id = COUNTER.fetch_add(1, Ordering::AcqRel);
         let name = format!("org.kde.StatusNotifierItem-{pid}-{id}");
 
-        let mut tmp_icon_counter = 0;
-        let (icon, tmp_icon_path) = builder
-            .icon
-            .map(NativeIcon::from)
-            .map(|icon| icon.write_to_disk((pid, id), &mut tmp_icon_counter))
-            .unzip();
-
-        let callback = Arc::new(Mutex::new(callback));
-        //"/home/simon/headset-controller/resources/icon.png"
         let conn = connection::Builder::session()?
             .name(name.clone())?
-            .serve_at(
-                ITEM_PATH,
-                StatusNotifierItem::new(icon.unwrap_or_default(), builder.tooltip.unwrap_or_default(), callback.clone()),
-            )?
-            .serve_at(MENU_PATH, DBusMenu::new(builder.menu.unwrap_or_else(Menu::empty), callback))?
-            .internal_executor(true)
+            .internal_executor(false)
             .build()
             .await?;
+        Ok((conn, id, name))
+    }
 
-        let (sender, receiver) = flume::unbounded();
-        let receiver_task = {
-            let connection = conn.clone();
-            conn.executor().spawn(
-                async move {
-                    while let Ok(event) = receiver.recv_async().await {
-                        match event {
-                            TrayUpdate::Menu(menu) => {
-                                let iface = connection
-                                    .object_server()
-                                    .interface::<_, DBusMenu<T>>(MENU_PATH)
-                                    .await
-                                    .unwrap();
-                                let iref = iface.get().await;
-                                iref.update_menu(menu, iface.signal_emitter())
-                                    .await
-                                    .unwrap();
-                            }
-                            TrayUpdate::Tooltip(tooltip) => {
-                                let iface = connection
-                                    .object_server()
-                                    .interface::<_, StatusNotifierItem<T>>(ITEM_PATH)
-                                    .await
-                                    .unwrap();
-                                let iref = iface.get().await;
-                                iref.update_tooltip(tooltip, iface.signal_emitter())
-                                    .await
-                                    .unwrap();
-                            }
-                            TrayUpdate::Icon(icon) => {
-                                let iface = connection
-                                    .object_server()
-                                    .interface::<_, StatusNotifierItem<T>>(ITEM_PATH)
-                                    .await
-                                    .unwrap();
-                                let iref = iface.get().await;
-                                iref.update_icon(icon, iface.signal_emitter())
-                                    .await
-                                    .unwrap();
-                            }
-                        }
-                    }
-                },
-                "event receiver",
-            )
+    pub fn new<F>(builder: TrayIconBuilder<T>, callback: F) -> TrayResult<Self>
+    where
+        F: FnMut(TrayEvent<T>) + Send + 'static
+    {
+        block_on(Self::new_async(builder, callback), "build_async")
+    }
+
+    pub async fn new_on_connection<F>(connection: &Connection, builder: TrayIconBuilder<T>, callback: F) -> TrayResult<Self>
+    where
+        F: FnMut(TrayEvent<T>) + Send + 'static
+    {
+        let id = COUNTER.fetch_add(1, Ordering::AcqRel);
+        let paths = ItemPaths::unique(id);
+        let service = paths.item.to_string();
+        let wait = builder.wait_for_watcher;
+        let callback: TrayCallback<T> = Arc::new(Mutex::new(callback));
+        let link = Link::Fixed(connection.clone());
+        let mut tray = Self::setup(link.clone(), id, paths.clone(), builder, callback.clone()).await?;
+        tray._cleanup = Some(ObjectCleanup::new::<T>(link, paths));
+        tray.register_with_watcher(connection, service, wait, callback)
+            .await?;
+        Ok(tray)
+    }
+
+    /// Serves the item and its menu at `paths` and starts processing updates
+    async fn setup(link: Link, id: u32, paths: ItemPaths, builder: TrayIconBuilder<T>, callback: TrayCallback<T>) -> TrayResult<Self> {
+        let pid = std::process::id();
+
+        let mut icon_cache = IconCache::new(builder.icon_dir, (pid, id), builder.icon_cache_size);
+        let icon = builder
+            .icon
+            .map(|icon| icon_cache.get(&NativeIcon::from(icon)))
+            .transpose()?;
+
+        let item = Arc::new(ItemState {
+            tooltip: Mutex::new(builder.tooltip),
+            icon: Mutex::new(icon.unwrap_or_default()),
+            ..Default::default()
+        });
+        let menu = DBusMenu::new(builder.menu, builder.auto_toggle, callback.clone());
+        let published = Published {
+            item: item.clone(),
+            menu: menu.state(),
+            status: Arc::new(Mutex::new(TrayStatus::NoHost))
         };
 
-        let proxy = StatusNotifierWatcherProxy::builder(&conn)
-            .path("/StatusNotifierWatcher")?
-            .build()
+        let conn = link.connection();
+        let object_server = conn.object_server();
+        object_server
+            .at(&paths.item, StatusNotifierItem::new(paths.menu.clone(), item, callback))
             .await?;
+        object_server.at(&paths.menu, menu).await?;
 
-        proxy.register_status_notifier_item(&name).await?;
+        if builder.cleanup_on_signal {
+            signals::enable_cleanup()?;
+        }
 
-        let register_task = {
-            conn.executor().spawn(
+        let (sender, receiver) = flume::unbounded::<TrayUpdateRequest<T>>();
+        let receiver_task = {
+            let executor = link.executor().clone();
+            let (link, paths) = (link.clone(), paths.clone());
+            let hook = builder.error_hook.clone();
+            executor.spawn(
                 async move {
-                    proxy
-                        .inner()
-                        .receive_owner_changed()
-                        .await?
-                        .then(|new_owner| {
-                            let proxy = &proxy;
-                            let name = &name;
-                            async move {
-                                match new_owner {
-                                    Some(_) => proxy.register_status_notifier_item(&name).await,
-                                    None => Ok(()),
-                                }
-                            }
-                        })
-                        .try_collect::<()>()
-                        .await
+                    receive_updates(receiver, hook, |update| {
+                        let connection = link.connection();
+                        let paths = &paths;
+                        async move { apply_update(&connection, paths, update).await }
+                    })
+                    .await
                 },
-                "statusnotifierwatcher watcher",
+                "event receiver"
             )
         };
 
+        let icon_cache = Arc::new(Mutex::new(icon_cache));
         Ok(Self {
-            id: (pid, id),
-            sender,
-            tmp_icon_file: Cell::new(tmp_icon_path.flatten()),
-            tmp_icon_counter: Cell::new(tmp_icon_counter),
+            link,
+            paths,
+            handle: NativeTrayHandle {
+                sender,
+                icon_cache: Arc::downgrade(&icon_cache),
+                published,
+                hook: builder.error_hook
+            },
+            _icon_cache: icon_cache,
             _update_task: receiver_task,
-            _register_task: register_task,
+            _register_task: None,
+            _handler_task: None,
+            _cleanup: None
         })
     }
 
-    pub fn new<F>(builder: TrayIconBuilder<T>, callback: F) -> TrayResult<Self>
-    where
-        F: FnMut(TrayEvent<T>) + Send + 'static,
+    pub async fn shutdown(mut self) -> TrayResult<()> {
+        if let Some(cleanup) = &mut self._cleanup {
+            cleanup.disarm();
+        }
+        if let Link::Shared(shared, item) = &self.link {
+            shared.unregister(item.name());
+        }
+        remove_objects::<T>(&self.link.connection(), &self.paths).await?;
+        Ok(())
+    }
+
+    pub fn close(self) -> TrayResult<()> {
+        // Trays that are driven by an event loop would otherwise wait for the loop that is blocked by this call
+        let executor = self.link.executor().clone();
+        block_on(drive(&executor, self.shutdown()), "shutdown().await")
+    }
+
+    /// Registers the item with the watcher of the connection and registers it again whenever the watcher restarts
+    ///
+    /// With `wait` the tray is created even if no watcher is running yet.
+    async fn register_with_watcher(&mut self, connection: &Connection, service: String, wait: bool, callback: TrayCallback<T>) -> TrayResult<()> {
+        let watcher = watcher_proxy(connection).await?;
+        let host = register_item(&watcher, &service, wait).await?;
+        let status = self.shared_status();
+        BusEvent::Host(host).update(&status);
+        self._register_task = Some(connection.executor().spawn(
+            follow_watcher(
+                watcher,
+                move || vec![service.clone()],
+                move |event| {
+                    event.update(&status);
+                    (callback.lock())(event.into_event())
+                }
+            ),
+            "statusnotifierwatcher watcher"
+        ));
+        Ok(())
+    }
+}
+
+/// Runs a blocking call of the tray to completion on the current thread
+///
+/// With the `tokio` feature the future runs on the surrounding tokio runtime, so zbus uses it for its tasks instead of starting its own thread.
+/// A current-thread runtime can't be blocked, the error then points to `alternative`, the async version of the call.
+#[cfg_attr(not(feature = "tokio"), allow(unused_variables))]
+fn block_on<F: Future<Output = TrayResult<R>>, R>(future: F, alternative: &str) -> TrayResult<R> {
+    #[cfg(feature = "tokio")]
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        return match handle.runtime_flavor() {
+            tokio::runtime::RuntimeFlavor::CurrentThread => Err(TrayError::new(
+                TrayErrorKind::Unsupported,
+                format!("Blocking calls require a multi-threaded tokio runtime, use `{alternative}` instead")
+            )),
+            _ => tokio::task::block_in_place(|| handle.block_on(future))
+        };
+    }
+    block_on_fallback(future)
+}
+
+#[cfg(feature = "async-io")]
+fn block_on_fallback<F: Future<Output = TrayResult<R>>, R>(future: F) -> TrayResult<R> {
+    async_io::block_on(future)
+}
+
+#[cfg(not(feature = "async-io"))]
+fn block_on_fallback<F: Future<Output = TrayResult<R>>, R>(_future: F) -> TrayResult<R> {
+    Err(TrayError::new(
+        TrayErrorKind::Unsupported,
+        "Creating a tray outside of a tokio runtime requires the `async-io` feature"
+    ))
+}
+
+/// Polls `future` while running the tasks of the connection on the same thread
+async fn drive<R>(executor: &Executor<'static>, future: impl Future<Output = R>) -> R {
+    let ticker = async {
+        loop {
+            executor.tick().await;
+        }
+    };
+    match select(pin!(future), pin!(ticker)).await {
+        Either::Left((result, _)) => result,
+        Either::Right((never, _)) => never
+    }
+}
+
+/// Waits until `deadline` or forever if there is none
+///
+/// Like in [block_on] the timer of tokio is only used within a tokio runtime, as the connection runs on async-io otherwise.
+async fn sleep_until(deadline: Option<Instant>) {
+    let Some(deadline) = deadline else {
+        return std::future::pending().await;
+    };
+    #[cfg(feature = "tokio")]
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return tokio::time::sleep_until(deadline.into()).await;
+    }
+    sleep_fallback(deadline).await
+}
+
+#[cfg(feature = "async-io")]
+async fn sleep_fallback(deadline: Instant) {
+    async_io::Timer::at(deadline).await;
+}
+
+#[cfg(not(feature = "async-io"))]
+async fn sleep_fallback(deadline: Instant) {
+    // Without async-io there is no connection outside of a tokio runtime
+    tokio::time::sleep_until(deadline.into()).await
+}
+
+/// Hides the item and removes the objects of a tray from the connection
+///
+/// The watcher only notices items that disappear together with their connection, so the host has to be told to hide it.
+async fn remove_objects<T: Clone + Send + 'static>(connection: &Connection, paths: &ItemPaths) -> zbus::Result<()> {
+    let object_server = connection.object_server();
+    let hidden = match object_server
+        .interface::<_, StatusNotifierItem<T>>(&paths.item)
+        .await
     {
-        async_io::block_on(Self::new_async(builder, callback))
+        Ok(item) => item.get().await.hide(item.signal_emitter()).await,
+        Err(err) => Err(err)
+    };
+    let item = object_server
+        .remove::<StatusNotifierItem<T>, _>(&paths.item)
+        .await;
+    let menu = object_server.remove::<DBusMenu<T>, _>(&paths.menu).await;
+    hidden.and(item).and(menu).map(drop)
+}
+
+/// Takes a tray off its connection when it is dropped
+///
+/// Trays on the shared executor only have to be forgotten, as their item disappears together with their connection.
+/// A connection that stays open after the tray is gone needs the objects of the tray removed instead.
+/// A connection of a tokio runtime spawns the removal with `tokio::spawn`, so the runtime the tray was created
+/// in is entered first. Otherwise dropping the tray on a thread without a runtime would panic.
+struct ObjectCleanup(Option<Box<dyn FnOnce() + Send>>);
+
+impl ObjectCleanup {
+    fn new<T: Clone + Send + 'static>(link: Link, paths: ItemPaths) -> Self {
+        #[cfg(feature = "tokio")]
+        let runtime = tokio::runtime::Handle::try_current().ok();
+        Self(Some(Box::new(move || match link {
+            Link::Shared(shared, item) => shared.unregister(item.name()),
+            Link::Fixed(connection) => {
+                #[cfg(feature = "tokio")]
+                let _guard = runtime.as_ref().map(tokio::runtime::Handle::enter);
+                let executor = connection.executor().clone();
+                executor
+                    .spawn(
+                        async move {
+                            remove_objects::<T>(&connection, &paths)
+                                .await
+                                .unwrap_or_else(|err| log::warn!("Failed to remove tray: {err}"));
+                        },
+                        "remove tray objects"
+                    )
+                    .detach();
+            }
+        })))
+    }
+
+    /// Stops the cleanup from running on drop
+    fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for ObjectCleanup {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.0.take() {
+            cleanup();
+        }
     }
 }
 
 impl<T> NativeTrayIcon<T> {
-    pub fn set_tooltip(&self, tooltip: Option<String>) {
-        self.sender
-            .send(TrayUpdate::Tooltip(tooltip.unwrap_or_default()))
-            .unwrap_or_else(|err| log::warn!("Failed to send update: {err}"));
+    /// The executor that runs the tasks of the DBus connection
+    #[cfg(any(feature = "calloop", feature = "glib"))]
+    pub fn executor(&self) -> &Executor<'static> {
+        self.link.executor()
     }
 
-    pub fn set_menu(&self, menu: Option<Menu<T>>) {
-        self.sender
-            .send(TrayUpdate::Menu(menu.unwrap_or_else(Menu::empty)))
-            .unwrap_or_else(|err| log::warn!("Failed to send update: {err}"));
-    }
-
-    pub fn set_icon(&self, icon: Option<Icon>) {
-        let mut counter = self.tmp_icon_counter.get();
-        let (icon, tmp_icon_path) = icon
-            .map(NativeIcon::from)
-            .map(|icon| icon.write_to_disk(self.id, &mut counter))
-            .unzip();
-        self.tmp_icon_counter.set(counter);
-        self.tmp_icon_file.set(tmp_icon_path.flatten());
-        self.sender
-            .send(TrayUpdate::Icon(icon.unwrap_or_default()))
-            .unwrap_or_else(|err| log::warn!("Failed to send update: {err}"));
+    pub fn spawn_handler<F: Future<Output = ()> + Send + 'static>(&mut self, handler: F) -> TrayResult<()> {
+        self._handler_task = Some(self.link.executor().spawn(handler, "event handler"));
+        Ok(())
+    }
+
+    pub fn handle(&self) -> NativeTrayHandle<T> {
+        self.handle.clone()
+    }
+
+    fn shared_status(&self) -> SharedStatus {
+        self.handle.published.status.clone()
     }
-}
 
-#[proxy(interface = "org.kde.StatusNotifierWatcher", assume_defaults = true)]
-trait StatusNotifierWatcher {
-    fn register_status_notifier_host(&self, service: &str) -> zbus::Result<()>;
+    pub fn tooltip(&self) -> Option<String> {
+        self.handle.tooltip()
+    }
 
-    fn register_status_notifier_item(&self, service: &str) -> zbus::Result<()>;
+    pub fn status(&self) -> TrayStatus {
+        self.handle.status()
+    }
 
-    #[zbus(signal)]
-    fn status_notifier_host_registered(&self) -> zbus::Result<()>;
+    pub fn update(&self, changes: TrayChanges<T>) -> TrayResult<()> {
+        self.handle.update(changes)
+    }
 
-    #[zbus(signal)]
-    fn status_notifier_host_unregistered(&self) -> zbus::Result<()>;
+    pub async fn update_async(&self, changes: TrayChanges<T>) -> TrayResult<()> {
+        self.handle.update_async(changes).await
+    }
 
-    #[zbus(signal)]
-    fn status_notifier_item_registered(&self, arg_1: &str) -> zbus::Result<()>;
+    pub fn set_tooltip(&self, tooltip: Option<String>) -> TrayResult<()> {
+        self.handle.set_tooltip(tooltip)
+    }
 
-    #[zbus(signal)]
-    fn status_notifier_item_unregistered(&self, arg_1: &str) -> zbus::Result<()>;
+    pub fn set_menu(&self, menu: Option<Menu<T>>) -> TrayResult<()> {
+        self.handle.set_menu(menu)
+    }
 
-    #[zbus(property)]
-    fn is_status_notifier_host_registered(&self) -> zbus::Result<bool>;
+    pub fn set_icon(&self, icon: Option<Icon>) -> TrayResult<()> {
+        self.handle.set_icon(icon)
+    }
 
-    #[zbus(property)]
-    fn protocol_version(&self) -> zbus::Result<i32>;
+    pub async fn set_tooltip_async(&self, tooltip: Option<String>) -> TrayResult<()> {
+        self.handle.set_tooltip_async(tooltip).await
+    }
 
-    #[zbus(property)]
-    fn registered_status_notifier_items(&self) -> zbus::Result<Vec<String>>;
+    pub async fn set_menu_async(&self, menu: Option<Menu<T>>) -> TrayResult<()> {
+        self.handle.set_menu_async(menu).await
+    }
+
+    pub async fn set_icon_async(&self, icon: Option<Icon>) -> TrayResult<()> {
+        self.handle.set_icon_async(icon).await
+    }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub enum NativeIcon {
-    #[allow(dead_code)]
-    Path(String),
-    Pixels(Vec<u8>),
+/// The error for updates that are sent after the tray is gone
+fn removed() -> TrayError {
+    TrayError::custom("The tray has been removed")
 }
 
-impl NativeIcon {
-    pub fn from_rgba(rgba: Vec<u8>, width: u32, height: u32) -> TrayResult<Self> {
-        let mut pixels = Vec::new();
-        let mut encoder = Encoder::new(&mut pixels, width, height);
-        encoder.set_color(ColorType::Rgba);
-        encoder.set_depth(BitDepth::Eight);
-        let mut writer = encoder.write_header().unwrap();
-        writer.write_image_data(&rgba).unwrap();
-        writer.finish().unwrap();
-        Ok(Self::Pixels(pixels))
+impl<T: Clone> NativeTrayIcon<T> {
+    pub fn menu(&self) -> Option<Menu<T>> {
+        self.handle.menu()
     }
 
-    pub fn from_png_bytes(bytes: &[u8]) -> TrayResult<Self> {
-        Ok(Self::Pixels(bytes.to_vec()))
+    pub fn snapshot(&self) -> TraySnapshot<T> {
+        self.handle.snapshot()
     }
-    fn write_to_disk(&self, id: (u32, u32), counter: &mut u32) -> (String, Option<TmpFileRaiiHandle>) {
-        match self {
-            NativeIcon::Path(path) => (path.clone(), None),
-            NativeIcon::Pixels(pixels) => {
-                let tmp_path = get_tmp_icon_path(id, *counter);
-                *counter += 1;
-                //std::fs::create_dir_all(&tmp_path).unwrap();
-                std::fs::write(&tmp_path, pixels).unwrap();
-                (tmp_path.clone(), Some(TmpFileRaiiHandle(tmp_path)))
-            }
+}
+
+/// What the tray currently publishes, shared with the objects that are served on the bus
+struct Published<T> {
+    item: Arc<ItemState>,
+    menu: MenuState<T>,
+    status: SharedStatus
+}
+
+impl<T> Clone for Published<T> {
+    fn clone(&self) -> Self {
+        Self {
+            item: self.item.clone(),
+            menu: self.menu.clone(),
+            status: self.status.clone()
         }
     }
 }
 
-struct TmpFileRaiiHandle(String);
+/// Sends updates to the tray from any thread
+///
+/// The icon cache is only borrowed, so a handle doesn't keep the icon files of a removed tray around.
+/// It also tells whether the tray still exists, as the update task may still be shutting down.
+pub struct NativeTrayHandle<T> {
+    sender: Sender<TrayUpdateRequest<T>>,
+    icon_cache: Weak<Mutex<IconCache>>,
+    published: Published<T>,
+    hook: Option<ErrorHook>
+}
 
-impl Drop for TmpFileRaiiHandle {
-    fn drop(&mut self) {
-        let path = self.0.as_str();
-        std::fs::remove_file(path).unwrap_or_else(|err| log::warn!("Failed to clean up icon file at {path}: {err}"));
+impl<T> Clone for NativeTrayHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            icon_cache: self.icon_cache.clone(),
+            published: self.published.clone(),
+            hook: self.hook.clone()
+        }
     }
 }
 
-fn get_tmp_icon_path((pid, id): (u32, u32), counter: u32) -> String {
-    static BASE_DIR: OnceLock<String> = OnceLock::new();
+impl<T> NativeTrayHandle<T> {
+    pub fn tooltip(&self) -> Option<String> {
+        self.published.item.tooltip.lock().clone()
+    }
+
+    pub fn status(&self) -> TrayStatus {
+        *self.published.status.lock()
+    }
+
+    fn icon(&self) -> Option<SnapshotIcon> {
+        // The files of a removed tray are gone
+        self.ensure_alive().ok()?;
+        self.published.item.icon.lock().describe()
+    }
+
+    /// Hands an error that happened in the background to the error hook of the tray
+    pub fn report(&self, err: TrayError) {
+        ErrorHook::report(self.hook.as_ref(), err);
+    }
+
+    fn ensure_alive(&self) -> TrayResult<()> {
+        crate::ensure!(self.icon_cache.strong_count() > 0, removed());
+        Ok(())
+    }
+
+    fn send(&self, update: TrayUpdate<T>) -> TrayResult<()> {
+        self.ensure_alive()?;
+        self.sender
+            .send(TrayUpdateRequest { update, done: None })
+            .map_err(|_| removed())
+    }
+
+    async fn send_async(&self, update: TrayUpdate<T>) -> TrayResult<()> {
+        self.ensure_alive()?;
+        let (done, result) = flume::bounded(1);
+        self.sender
+            .send_async(TrayUpdateRequest { update, done: Some(done) })
+            .await
+            .map_err(|_| removed())?;
+        result.recv_async().await.map_err(|_| removed())?
+    }
+
+    fn write_icon(&self, icon: Option<Icon>) -> TrayResult<ItemIcon> {
+        let Some(icon) = icon else {
+            return Ok(ItemIcon::None);
+        };
+        let cache = self.icon_cache.upgrade().ok_or_else(removed)?;
+        let icon = cache.lock().get(&NativeIcon::from(icon))?;
+        Ok(icon)
+    }
+
+    /// Turns the public change set into an update, writing the icon if necessary
+    fn prepare(&self, changes: TrayChanges<T>) -> TrayResult<TrayUpdate<T>> {
+        Ok(TrayUpdate {
+            tooltip: changes.tooltip,
+            icon: changes.icon.map(|icon| self.write_icon(icon)).transpose()?,
+            menu: changes.menu
+        })
+    }
+
+    pub fn update(&self, changes: TrayChanges<T>) -> TrayResult<()> {
+        self.send(self.prepare(changes)?)
+    }
+
+    pub async fn update_async(&self, changes: TrayChanges<T>) -> TrayResult<()> {
+        self.send_async(self.prepare(changes)?).await
+    }
 
-    let base = BASE_DIR.get_or_init(|| {
-        let base = std::env::var("XDG_RUNTIME_DIR")
-            .map(PathBuf::from)
-            .ok()
-            .unwrap_or_else(std::env::temp_dir)
-            .join("betrayer");
-        std::fs::create_dir_all(&base).expect("Failed to create icon tmp dir");
-        log::trace!("Using {base:?} as tmp dir for icons");
-        base.to_str()
-            .expect("Non UTF-8 paths are currently not supported")
-            .to_string()
-    });
-    format!("{base}/icon-{pid}-{id}-{counter}.png")
+    pub fn set_tooltip(&self, tooltip: Option<String>) -> TrayResult<()> {
+        self.update(TrayChanges::from_tooltip(tooltip))
+    }
+
+    pub fn set_menu(&self, menu: Option<Menu<T>>) -> TrayResult<()> {
+        self.update(TrayChanges::from_menu(menu))
+    }
+
+    pub fn set_icon(&self, icon: Option<Icon>) -> TrayResult<()> {
+        self.update(TrayChanges::from_icon(icon))
+    }
+
+    pub async fn set_tooltip_async(&self, tooltip: Option<String>) -> TrayResult<()> {
+        self.update_async(TrayChanges::from_tooltip(tooltip)).await
+    }
+
+    pub async fn set_menu_async(&self, menu: Option<Menu<T>>) -> TrayResult<()> {
+        self.update_async(TrayChanges::from_menu(menu)).await
+    }
+
+    pub async fn set_icon_async(&self, icon: Option<Icon>) -> TrayResult<()> {
+        self.update_async(TrayChanges::from_icon(icon)).await
+    }
+}
+
+impl<T: Clone> NativeTrayHandle<T> {
+    pub fn menu(&self) -> Option<Menu<T>> {
+        self.published.menu.menu()
+    }
+
+    pub fn snapshot(&self) -> TraySnapshot<T> {
+        TraySnapshot {
+            tooltip: self.tooltip(),
+            icon: self.icon(),
+            menu: self
+                .menu()
+                .map(|menu| menu.into_iter().map(SnapshotItem::from).collect()),
+            status: self.status()
+        }
+    }
 }
 
 pub type PlatformError = zbus::Error;
+
+pub fn os_error_kind(err: &PlatformError) -> TrayErrorKind {
+    match err {
+        zbus::Error::InputOutput(_) | zbus::Error::Address(_) | zbus::Error::Handshake(_) | zbus::Error::Connection(..) => TrayErrorKind::Connection,
+        _ => TrayErrorKind::Other
+    }
+}
 impl From<PlatformError> for ErrorSource {
     fn from(value: PlatformError) -> Self {
         ErrorSource::Os(value)
     }
 }
+
+#[cfg(all(test, feature = "async-io"))]
+pub(crate) mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use futures_util::future::try_join;
+    use futures_util::StreamExt;
+    use zbus::message::Type;
+    use zbus::zvariant::{OwnedValue, Value};
+    use zbus::{Guid, Message, MessageStream};
+
+    use super::*;
+    use crate::platform::linux::test_bus::session_bus;
+    pub(crate) use crate::platform::linux::test_bus::wait_until;
+    use crate::{MenuItem, Support};
+
+    type ToolTip = (String, Vec<(i32, i32, Vec<u8>)>, String, String);
+
+    /// Serves a tray on one end of a private connection, so tests don't need a session bus
+    ///
+    /// The other end of the connection is returned to call the objects of the tray, the tray stops working once it is dropped.
+    pub(crate) fn serve<T, F>(builder: TrayIconBuilder<T>, callback: F) -> (NativeTrayIcon<T>, Connection)
+    where
+        T: Clone + Send + 'static,
+        F: FnMut(TrayEvent<T>) + Send + 'static
+    {
+        async_io::block_on(async {
+            let (server, client) = UnixStream::pair().unwrap();
+            let server = connection::Builder::async_io_unix_stream(server)
+                .server(Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at("/ready", Ready)
+                .unwrap()
+                .build();
+            let client = connection::Builder::async_io_unix_stream(client)
+                .p2p()
+                .build();
+            let (server, client) = try_join(server, client).await.unwrap();
+            let id = COUNTER.fetch_add(1, Ordering::AcqRel);
+            let callback: TrayCallback<T> = Arc::new(Mutex::new(callback));
+            let tray = NativeTrayIcon::setup(Link::Fixed(server), id, ItemPaths::fixed(), builder, callback)
+                .await
+                .unwrap();
+            (tray, client)
+        })
+    }
+
+    /// Served from the start, so building the connection waits until its object server answers calls
+    ///
+    /// The object server of a connection is started in the background otherwise and calls that arrive before are lost.
+    struct Ready;
+
+    #[zbus::interface(name = "com.github.betrayer.Ready")]
+    impl Ready {}
+
+    /// Clicks a menu entry like a tray host would, through the other end of a connection from [serve]
+    pub(crate) fn click(peer: &Connection, id: i32) {
+        let body = (id, "clicked", Value::from(0i32), 0u32);
+        async_io::block_on(peer.call_method(None::<&str>, MENU_PATH, Some("com.canonical.dbusmenu"), "Event", &body)).unwrap();
+    }
+
+    /// Reads a property like a tray host would, through the other end of a connection from [serve]
+    pub(crate) fn property(peer: &Connection, path: &str, interface: &str, name: &str) -> OwnedValue {
+        let reply =
+            async_io::block_on(peer.call_method(None::<&str>, path, Some("org.freedesktop.DBus.Properties"), "Get", &(interface, name))).unwrap();
+        reply.body().deserialize().unwrap()
+    }
+
+    /// Waits for the next signal called `member` on `stream`
+    pub(crate) fn next_signal(stream: &mut MessageStream, member: &str) -> Message {
+        let signal = async {
+            while let Some(message) = stream.next().await {
+                let message = message.unwrap();
+                if message.message_type() == Type::Signal && message.header().member().is_some_and(|name| name == member) {
+                    return message;
+                }
+            }
+            panic!("The connection closed before {member} was sent")
+        };
+        let timeout = async {
+            async_io::Timer::after(Duration::from_secs(5)).await;
+            panic!("{member} was not sent")
+        };
+        async_io::block_on(async {
+            match select(pin!(signal), pin!(timeout)).await {
+                Either::Left((message, _)) => message,
+                Either::Right((never, _)) => never
+            }
+        })
+    }
+
+    #[test]
+    fn getters_return_what_was_set() {
+        let (tray, _peer) = serve(TrayIconBuilder::<u32>::new().with_tooltip(""), |_| {});
+        assert_eq!(tray.tooltip(), Some(String::new()));
+        assert_eq!(tray.menu(), None);
+        async_io::block_on(async {
+            tray.set_menu_async(Some(Menu::empty())).await.unwrap();
+            assert_eq!(tray.menu(), Some(Menu::empty()));
+            tray.set_tooltip_async(None).await.unwrap();
+            assert_eq!(tray.tooltip(), None);
+            tray.set_menu_async(None).await.unwrap();
+            assert_eq!(tray.menu(), None);
+        });
+    }
+
+    #[test]
+    fn async_setters_resolve_once_the_host_was_notified() {
+        let (tray, peer) = serve(TrayIconBuilder::<u32>::new(), |_| {});
+        let mut signals = MessageStream::from(&peer);
+        async_io::block_on(tray.set_tooltip_async(Some(String::from("a")))).unwrap();
+        next_signal(&mut signals, "NewToolTip");
+        async_io::block_on(tray.set_menu_async(Some(Menu::new([MenuItem::button("a", 1)])))).unwrap();
+        next_signal(&mut signals, "LayoutUpdated");
+
+        let tooltip = property(&peer, ITEM_PATH, "org.kde.StatusNotifierItem", "ToolTip");
+        let tooltip = ToolTip::try_from(tooltip).unwrap();
+        assert_eq!(tooltip.2, "a");
+    }
+
+    fn tooltip_update(tooltip: &str) -> TrayUpdate<u32> {
+        TrayUpdate {
+            tooltip: Some(Some(tooltip.to_owned())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merged_updates_keep_the_newest_changes() {
+        let mut update = TrayUpdate::<u32> {
+            icon: Some(ItemIcon::Name(String::from("first"))),
+            ..tooltip_update("first")
+        };
+        update.merge(TrayUpdate {
+            tooltip: Some(None),
+            menu: Some(Some(Menu::empty())),
+            ..Default::default()
+        });
+        update.merge(TrayUpdate::default());
+        assert_eq!(update.tooltip, Some(None));
+        assert_eq!(update.icon, Some(ItemIcon::Name(String::from("first"))));
+        assert_eq!(update.menu, Some(Some(Menu::empty())));
+    }
+
+    #[test]
+    fn queued_updates_are_applied_at_once() {
+        let (sender, receiver) = flume::unbounded();
+        let mut results = Vec::new();
+        for tooltip in ["a", "b", "c"] {
+            let (done, result) = flume::bounded(1);
+            sender
+                .send(TrayUpdateRequest {
+                    update: tooltip_update(tooltip),
+                    done: Some(done)
+                })
+                .unwrap();
+            results.push(result);
+        }
+        sender
+            .send(TrayUpdateRequest {
+                update: TrayUpdate::default(),
+                done: None
+            })
+            .unwrap();
+        drop(sender);
+
+        let mut applied = Vec::new();
+        async_io::block_on(receive_updates(receiver, None, |update| {
+            applied.push(update.tooltip);
+            async { Ok(()) }
+        }));
+        assert_eq!(applied, [Some(Some(String::from("c")))]);
+        for result in results {
+            assert!(result.try_recv().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn failed_updates_reach_every_waiter_and_the_hook() {
+        let (sender, receiver) = flume::unbounded();
+        let (done, result) = flume::bounded(1);
+        sender
+            .send(TrayUpdateRequest {
+                update: tooltip_update("a"),
+                done: Some(done)
+            })
+            .unwrap();
+        sender
+            .send(TrayUpdateRequest {
+                update: tooltip_update("b"),
+                done: None
+            })
+            .unwrap();
+        drop(sender);
+
+        let reported = Arc::new(AtomicU32::new(0));
+        let hook = ErrorHook::new({
+            let reported = reported.clone();
+            move |_| {
+                reported.fetch_add(1, Ordering::AcqRel);
+            }
+        });
+        async_io::block_on(receive_updates(receiver, Some(hook), |_| async { Err(zbus::Error::Unsupported) }));
+        assert!(result.try_recv().unwrap().is_err());
+        assert_eq!(reported.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn every_tray_owns_a_name_that_goes_away_with_it() {
+        let Some(bus) = session_bus() else {
+            return;
+        };
+        let first = async_io::block_on(TrayIconBuilder::<u32>::new().build_async(|_| {})).unwrap();
+        let second = async_io::block_on(TrayIconBuilder::<u32>::new().build_async(|_| {})).unwrap();
+        let [(first_name, _), (second_name, _)] = <[_; 2]>::try_from(bus.items()).unwrap();
+        assert_ne!(first_name, second_name);
+        assert!(bus.has_item(&first_name, ITEM_PATH));
+        assert!(bus.has_item(&second_name, ITEM_PATH));
+
+        drop(first);
+        wait_until(|| !bus.has_owner(&first_name));
+        assert!(bus.has_item(&second_name, ITEM_PATH));
+        drop(second);
+        wait_until(|| !bus.has_owner(&second_name));
+    }
+
+    #[test]
+    fn shutdown_removes_the_tray() {
+        let Some(bus) = session_bus() else {
+            return;
+        };
+        let tray = async_io::block_on(TrayIconBuilder::<u32>::new().build_async(|_| {})).unwrap();
+        let [(name, _)] = <[_; 1]>::try_from(bus.items()).unwrap();
+        assert!(bus.has_item(&name, ITEM_PATH));
+
+        async_io::block_on(tray.shutdown()).unwrap();
+        wait_until(|| !bus.has_owner(&name));
+    }
+
+    #[test]
+    fn close_removes_the_objects_of_a_tray() {
+        let (tray, peer) = serve(TrayIconBuilder::<u32>::new().with_menu(Menu::empty()), |_| {});
+        tray.close().unwrap();
+        for (path, interface) in [(ITEM_PATH, "org.kde.StatusNotifierItem"), (MENU_PATH, "com.canonical.dbusmenu")] {
+            let reply = async_io::block_on(peer.call_method(None::<&str>, path, Some("org.freedesktop.DBus.Properties"), "GetAll", &interface));
+            assert!(reply.is_err(), "{path} is still served");
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn close_points_to_shutdown_on_a_current_thread_runtime() {
+        let Some(_bus) = session_bus() else {
+            return;
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let err = runtime.block_on(async {
+            let tray = TrayIconBuilder::<u32>::new()
+                .build_async(|_| {})
+                .await
+                .unwrap();
+            tray.close().unwrap_err()
+        });
+        assert_eq!(err.kind(), TrayErrorKind::Unsupported);
+        assert!(err.to_string().contains("shutdown().await"), "{err}");
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn trays_of_a_runtime_can_be_dropped_on_other_threads() {
+        let Some(bus) = session_bus() else {
+            return;
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (shared, external) = runtime.block_on(async {
+            let connection = Connection::session().await.unwrap();
+            let shared = TrayIconBuilder::<u32>::new().build_async(|_| {}).await;
+            let external = TrayIconBuilder::<u32>::new()
+                .build_on_connection(&connection, |_| {})
+                .await;
+            (shared.unwrap(), external.unwrap())
+        });
+        let [(name, _), (path, sender)] = <[_; 2]>::try_from(bus.items()).unwrap();
+        assert!(bus.has_item(&sender, &path));
+
+        std::thread::spawn(move || drop((shared, external)))
+            .join()
+            .unwrap();
+        wait_until(|| !bus.has_owner(&name) && !bus.has_item(&sender, &path));
+    }
+
+    #[test]
+    fn trays_are_published_again_after_the_bus_restarts() {
+        let Some(mut bus) = session_bus() else {
+            return;
+        };
+        let (sender, events) = flume::unbounded();
+        let builder = TrayIconBuilder::<u32>::new()
+            .with_tooltip("online")
+            .with_error_hook(|_| {});
+        let tray = async_io::block_on(builder.build_async(move |event| sender.send(event).unwrap())).unwrap();
+        let handle = tray.handle();
+        let [(name, _)] = <[_; 1]>::try_from(bus.items()).unwrap();
+        let mut connection_events = events.iter().filter_map(|event| match event {
+            TrayEvent::Connection(state) => Some(state),
+            _ => None
+        });
+
+        bus.stop();
+        assert_eq!(connection_events.next(), Some(ConnectionState::Disconnected));
+        handle.set_tooltip("offline").unwrap();
+        bus.start();
+        assert_eq!(connection_events.next(), Some(ConnectionState::Reconnected));
+
+        // Registered again with the new watcher and served at the same path
+        let [(registered, _)] = <[_; 1]>::try_from(bus.items()).unwrap();
+        assert_eq!(registered, name);
+        let tooltip = bus.item_property(&name, ITEM_PATH, "ToolTip").unwrap();
+        assert_eq!(ToolTip::try_from(tooltip).unwrap().2, "offline");
+        assert_eq!(tray.status(), TrayStatus::Visible);
+    }
+
+    #[test]
+    fn probe_checks_for_watcher_and_host() {
+        let Some(bus) = session_bus() else {
+            return;
+        };
+        assert_eq!(probe().unwrap(), Support::Available);
+        bus.set_host(false);
+        assert_eq!(probe().unwrap(), Support::NoHost);
+        bus.set_watcher(false);
+        assert_eq!(probe().unwrap(), Support::NoWatcher);
+        bus.set_watcher(true);
+    }
+
+    #[test]
+    fn host_changes_are_reported() {
+        let Some(bus) = session_bus() else {
+            return;
+        };
+        let (sender, events) = flume::unbounded();
+        let tray = async_io::block_on(TrayIconBuilder::<u32>::new().build_async(move |event| sender.send(event).unwrap())).unwrap();
+        assert_eq!(tray.status(), TrayStatus::Visible);
+
+        bus.set_host(false);
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)), Ok(TrayEvent::HostUnavailable));
+        assert_eq!(tray.status(), TrayStatus::NoHost);
+        bus.set_host(true);
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)), Ok(TrayEvent::HostAvailable));
+        assert_eq!(tray.status(), TrayStatus::Visible);
+    }
+
+    #[test]
+    fn trays_can_wait_for_a_watcher() {
+        let Some(bus) = session_bus() else {
+            return;
+        };
+        bus.set_watcher(false);
+        let missing = async_io::block_on(TrayIconBuilder::<u32>::new().build_async(|_| {}));
+        assert_eq!(missing.err().map(|err| err.kind()), Some(TrayErrorKind::NoHost));
+
+        let (sender, events) = flume::unbounded();
+        let builder = TrayIconBuilder::<u32>::new().with_wait_for_watcher(true);
+        let tray = async_io::block_on(builder.build_async(move |event| sender.send(event).unwrap())).unwrap();
+        assert_eq!(tray.status(), TrayStatus::NoHost);
+        assert!(bus.items().is_empty());
+
+        bus.set_watcher(true);
+        assert_eq!(events.recv_timeout(Duration::from_secs(5)), Ok(TrayEvent::HostAvailable));
+        assert_eq!(bus.items().len(), 1);
+        assert_eq!(tray.status(), TrayStatus::Visible);
+    }
+
+    #[test]
+    fn hidden_items_report_a_passive_status() {
+        let (tray, peer) = serve(TrayIconBuilder::<u32>::new(), |_| {});
+        let status = || String::try_from(property(&peer, ITEM_PATH, "org.kde.StatusNotifierItem", "Status")).unwrap();
+        assert_eq!(status(), "Active");
+        let mut signals = MessageStream::from(&peer);
+        async_io::block_on(async {
+            let item = tray
+                .link
+                .connection()
+                .object_server()
+                .interface::<_, StatusNotifierItem<u32>>(ITEM_PATH)
+                .await
+                .unwrap();
+            let hidden = item.get().await.hide(item.signal_emitter()).await;
+            hidden
+        })
+        .unwrap();
+        let signal = next_signal(&mut signals, "NewStatus");
+        assert_eq!(signal.body().deserialize::<&str>().unwrap(), "Passive");
+        assert_eq!(status(), "Passive");
+    }
+
+    #[test]
+    fn snapshot_describes_icon() {
+        let dir = tempfile::tempdir().unwrap();
+        let builder = TrayIconBuilder::<u32>::new()
+            .with_icon(Icon::from_rgba(vec![255; 16 * 8 * 4], 16, 8).unwrap())
+            .with_icon_dir(dir.path());
+        let (tray, _peer) = serve(builder, |_| {});
+        match tray.snapshot().icon {
+            Some(SnapshotIcon::Image { size, .. }) => assert_eq!(size, Some((16, 8))),
+            other => panic!("unexpected icon {other:?}")
+        }
+        async_io::block_on(tray.set_icon_async(None)).unwrap();
+        assert_eq!(tray.snapshot().icon, None);
+
+        drop(tray);
+        dir.close().unwrap();
+    }
+}
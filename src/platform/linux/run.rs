@@ -0,0 +1,110 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use flume::Receiver;
+use futures_util::{select_biased, FutureExt};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::{Handle, Signals};
+
+use crate::error::{TrayError, TrayErrorKind, TrayResult};
+use crate::platform::linux::{drive, signals, sleep_until, ItemPaths, Link, NativeTrayIcon, TrayCallback};
+use crate::run::RunLoop;
+use crate::{forward_events, TrayIcon, TrayIconBuilder};
+
+pub fn run<T: Clone + Send + 'static>(builder: TrayIconBuilder<T>, mut state: RunLoop<T>) -> TrayResult<()> {
+    let signals = SignalForwarder::new()?;
+    block_on(async move {
+        // Without the internal executor the connection only makes progress while its executor is ticked below
+        let (connection, id, name) = NativeTrayIcon::<T>::connect().await?;
+        let main = async {
+            let (sender, events) = flume::unbounded();
+            let wait = builder.wait_for_watcher;
+            let callback: TrayCallback<T> = Arc::new(parking_lot::Mutex::new(forward_events(sender)));
+            let mut native = NativeTrayIcon::setup(Link::Fixed(connection.clone()), id, ItemPaths::fixed(), builder, callback.clone()).await?;
+            native
+                .register_with_watcher(&connection, name, wait, callback)
+                .await?;
+            let tray = TrayIcon(native);
+            state.start(&tray);
+            while !state.exiting() {
+                select_biased! {
+                    signal = signals.receiver.recv_async() => {
+                        log::debug!("Received signal {}, stopping the tray", signal.unwrap_or_default());
+                        break;
+                    },
+                    event = events.recv_async() => if let Ok(event) = event {
+                        state.handle_event(&tray, event);
+                    },
+                    _ = sleep_until(state.next_deadline()).fuse() => state.fire_timers(&tray),
+                    _ = connection.closed().fuse() => return Err(TrayError::new(TrayErrorKind::Connection, "Lost the connection to the session bus"))
+                }
+            }
+            Ok(())
+        };
+        drive(connection.executor(), main).await
+    })
+}
+
+#[cfg(feature = "tokio")]
+fn block_on<R>(future: impl Future<Output = TrayResult<R>>) -> TrayResult<R> {
+    crate::ensure!(
+        tokio::runtime::Handle::try_current().is_err(),
+        TrayError::new(TrayErrorKind::Unsupported, "The tray loop can't be started from within a tokio runtime")
+    );
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| TrayError::custom(format!("Failed to create the tokio runtime: {err}")))?
+        .block_on(future)
+}
+
+#[cfg(not(feature = "tokio"))]
+fn block_on<R>(future: impl Future<Output = TrayResult<R>>) -> TrayResult<R> {
+    async_io::block_on(future)
+}
+
+/// Forwards `SIGINT` and `SIGTERM` to a channel while the loop is running
+struct SignalForwarder {
+    receiver: Receiver<i32>,
+    handle: Handle,
+    thread: Option<JoinHandle<()>>
+}
+
+impl SignalForwarder {
+    fn new() -> TrayResult<Self> {
+        signals::enter_loop();
+        Self::spawn().inspect_err(|_| signals::leave_loop())
+    }
+
+    fn spawn() -> TrayResult<Self> {
+        let mut signals = Signals::new([SIGINT, SIGTERM]).map_err(|err| TrayError::custom(format!("Failed to register signal handlers: {err}")))?;
+        let handle = signals.handle();
+        let (sender, receiver) = flume::bounded(1);
+        let thread = std::thread::Builder::new()
+            .name(String::from("betrayer signal forwarder"))
+            .spawn(move || {
+                for signal in signals.forever() {
+                    let _ = sender.try_send(signal);
+                }
+            })
+            .map_err(|err| TrayError::custom(format!("Failed to spawn signal thread: {err}")))?;
+        Ok(Self {
+            receiver,
+            handle,
+            thread: Some(thread)
+        })
+    }
+}
+
+impl Drop for SignalForwarder {
+    fn drop(&mut self) {
+        signals::leave_loop();
+        self.handle.close();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::warn!("The signal thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use std::future::Future;
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use parking_lot::Mutex;
+use zbus::{connection, Connection, Executor, Task};
+
+use crate::error::TrayResult;
+use crate::platform::linux::host::{follow_watcher, host_registered, register_item, watcher_proxy, StatusNotifierWatcherProxy};
+use crate::platform::linux::item::StatusNotifierItem;
+use crate::platform::linux::menu::DBusMenu;
+use crate::platform::linux::{sleep_until, BusEvent, ItemPaths, SharedStatus, TrayCallback};
+use crate::ConnectionState;
+
+static SHARED: OnceLock<futures_util::lock::Mutex<Weak<SharedConnection>>> = OnceLock::new();
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// The session bus connection that takes care of all trays of the process that don't bring their own connection
+///
+/// The trays share its executor thread and its watcher subscription. Each tray still has an [ItemConnection]
+/// of its own, as watchers only drop items once the bus name they were registered with goes away.
+/// The connection is closed once the last tray is dropped.
+///
+/// When the connection is lost new connections are opened and all trays are published on them again.
+/// The tasks of the trays keep running on the executor of the first connection the whole time.
+pub struct SharedConnection {
+    executor: Executor<'static>,
+    bus: Mutex<Bus>,
+    items: Arc<Mutex<Vec<SharedItem>>>,
+    supervisor: Mutex<Option<Task<()>>>
+}
+
+/// A single connection to the session bus and the tasks that belong to it
+struct Bus {
+    connection: Connection,
+    watcher: StatusNotifierWatcherProxy<'static>,
+    _register_task: Task<zbus::Result<()>>,
+    _ticker: Option<Task<()>>
+}
+
+/// Copies the objects of a tray from the lost connection to the new one
+type Republish = fn(&Connection, &Connection, &ItemPaths) -> BoxFuture<'static, zbus::Result<()>>;
+
+struct SharedItem {
+    connection: Arc<ItemConnection>,
+    paths: ItemPaths,
+    republish: Republish,
+    status: SharedStatus,
+    notify: Arc<dyn Fn(BusEvent) + Send + Sync>
+}
+
+impl SharedConnection {
+    /// Returns the connection of the process, opening it if no tray currently uses it
+    pub async fn get() -> TrayResult<Arc<Self>> {
+        let mut shared = SHARED.get_or_init(Default::default).lock().await;
+        if let Some(existing) = shared.upgrade() {
+            return Ok(existing);
+        }
+
+        let connection = connection::Builder::session()?
+            // Only starts a thread for the async-io backend, inside a tokio runtime all tasks are spawned with `tokio::spawn`
+            .internal_executor(true)
+            .build()
+            .await?;
+        let executor = connection.executor().clone();
+        let items = Arc::new(Mutex::new(Vec::new()));
+        let bus = Bus::new(connection, None, &items).await?;
+
+        let new = Arc::new(Self {
+            executor,
+            bus: Mutex::new(bus),
+            items,
+            supervisor: Mutex::new(None)
+        });
+        *new.supervisor.lock() = Some(
+            new.executor
+                .spawn(supervise(Arc::downgrade(&new)), "connection supervisor")
+        );
+        *shared = Arc::downgrade(&new);
+        Ok(new)
+    }
+
+    /// The connection that is currently in use, this changes when the connection had to be restored
+    pub fn connection(&self) -> Connection {
+        self.bus.lock().connection.clone()
+    }
+
+    /// The executor that runs the tasks of all trays
+    pub fn executor(&self) -> &Executor<'static> {
+        &self.executor
+    }
+
+    /// Opens the connection of a new tray, which owns `name` and runs on the executor of the shared connection
+    pub async fn open_item(&self, name: String) -> TrayResult<Arc<ItemConnection>> {
+        let bus = ItemConnection::open(&name, &self.executor).await?;
+        Ok(Arc::new(ItemConnection { name, bus: Mutex::new(bus) }))
+    }
+
+    /// Registers an item with the watcher, it is registered again whenever the watcher restarts or the connection is restored
+    ///
+    /// With `wait` a missing watcher is not an error.
+    pub async fn register<T: Clone + Send + 'static>(
+        &self, connection: Arc<ItemConnection>, paths: ItemPaths, wait: bool, callback: TrayCallback<T>, status: SharedStatus
+    ) -> TrayResult<()> {
+        let service = connection.name.clone();
+        let notify = {
+            let status = status.clone();
+            Arc::new(move |event: BusEvent| {
+                event.update(&status);
+                (callback.lock())(event.into_event())
+            })
+        };
+        self.items.lock().push(SharedItem {
+            connection,
+            paths,
+            republish: republish::<T>,
+            status: status.clone(),
+            notify
+        });
+        let watcher = self.bus.lock().watcher.clone();
+        let host = register_item(&watcher, &service, wait)
+            .await
+            .inspect_err(|_| self.unregister(&service))?;
+        BusEvent::Host(host).update(&status);
+        Ok(())
+    }
+
+    /// Stops publishing an item again when the watcher restarts or the connection is restored
+    ///
+    /// The connection of the item is closed once the tray drops it as well.
+    pub fn unregister(&self, service: &str) {
+        self.items
+            .lock()
+            .retain(|item| item.connection.name != service);
+    }
+
+    /// Opens new connections and publishes all items on them
+    async fn reconnect(&self) -> TrayResult<()> {
+        let connection = connection::Builder::session()?
+            .internal_executor(false)
+            .build()
+            .await?;
+        let ticker = tick(&self.executor, &connection);
+
+        let items = self
+            .items
+            .lock()
+            .iter()
+            .map(|item| (item.connection.clone(), item.paths.clone(), item.republish))
+            .collect::<Vec<_>>();
+        for (item, paths, republish) in items {
+            let old = item.connection();
+            let new = ItemConnection::open(&item.name, &self.executor).await?;
+            republish(&old, &new.0, &paths).await?;
+            *item.bus.lock() = new;
+        }
+
+        let bus = Bus::new(connection, Some(ticker), &self.items).await?;
+        for service in services(&self.items) {
+            // A watcher that isn't back yet picks the items up once it appears
+            bus.watcher
+                .register_status_notifier_item(&service)
+                .await
+                .unwrap_or_else(|err| log::warn!("Failed to register {service} with the watcher: {err}"));
+        }
+        let host = host_registered(&bus.watcher).await;
+        for item in self.items.lock().iter() {
+            BusEvent::Host(host).update(&item.status);
+        }
+        *self.bus.lock() = bus;
+        Ok(())
+    }
+}
+
+impl Bus {
+    async fn new(connection: Connection, ticker: Option<Task<()>>, items: &Arc<Mutex<Vec<SharedItem>>>) -> TrayResult<Self> {
+        let watcher = watcher_proxy(&connection).await?;
+        let register_task = {
+            let (registered, notified) = (items.clone(), items.clone());
+            connection.executor().spawn(
+                follow_watcher(watcher.clone(), move || services(&registered), move |event| notify(&notified, event)),
+                "statusnotifierwatcher watcher"
+            )
+        };
+        Ok(Self {
+            connection,
+            watcher,
+            _register_task: register_task,
+            _ticker: ticker
+        })
+    }
+}
+
+/// The connection of a single tray that owns the well-known name the tray is registered with
+///
+/// Like the connections that replace a lost shared connection, it is driven by the executor of the trays.
+pub struct ItemConnection {
+    name: String,
+    bus: Mutex<(Connection, Task<()>)>
+}
+
+impl ItemConnection {
+    /// Opens a connection that owns `name` and spawns the task that drives it on `executor`
+    async fn open(name: &str, executor: &Executor<'static>) -> TrayResult<(Connection, Task<()>)> {
+        let connection = connection::Builder::session()?
+            .name(name.to_owned())?
+            .internal_executor(false)
+            .build()
+            .await?;
+        let ticker = tick(executor, &connection);
+        Ok((connection, ticker))
+    }
+
+    /// The connection that is currently in use, this changes when the connection had to be restored
+    pub fn connection(&self) -> Connection {
+        self.bus.lock().0.clone()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Runs the tasks of `connection` on `executor`, which outlives every single connection
+fn tick(executor: &Executor<'static>, connection: &Connection) -> Task<()> {
+    let ticked = connection.executor().clone();
+    executor.spawn(
+        async move {
+            loop {
+                ticked.tick().await;
+            }
+        },
+        "connection ticker"
+    )
+}
+
+/// The names of all items, which they are registered with
+fn services(items: &Mutex<Vec<SharedItem>>) -> Vec<String> {
+    items
+        .lock()
+        .iter()
+        .map(|item| item.connection.name.clone())
+        .collect()
+}
+
+/// Passes `event` to the callbacks of all items
+fn notify(items: &Mutex<Vec<SharedItem>>, event: BusEvent) {
+    let notify = items
+        .lock()
+        .iter()
+        .map(|item| item.notify.clone())
+        .collect::<Vec<_>>();
+    for notify in notify {
+        notify(event);
+    }
+}
+
+/// Waits for the connection to close and restores it with an increasing delay between attempts
+async fn supervise(shared: Weak<SharedConnection>) {
+    loop {
+        let Some(connection) = shared.upgrade().map(|shared| shared.connection()) else {
+            return;
+        };
+        connection.closed().await;
+        drop(connection);
+
+        log::warn!("Lost the connection to the session bus, trying to reconnect");
+        let Some(current) = shared.upgrade() else {
+            return;
+        };
+        notify(&current.items, BusEvent::Connection(ConnectionState::Disconnected));
+        drop(current);
+
+        let reconnected = with_backoff(|| {
+            let shared = shared.clone();
+            async move {
+                let Some(current) = shared.upgrade() else {
+                    return Some(None);
+                };
+                match current.reconnect().await {
+                    Ok(()) => Some(Some(current)),
+                    Err(err) => {
+                        log::debug!("Failed to reconnect to the session bus: {err}");
+                        None
+                    }
+                }
+            }
+        })
+        .await;
+        let Some(current) = reconnected else {
+            return;
+        };
+        log::info!("Reconnected to the session bus");
+        notify(&current.items, BusEvent::Connection(ConnectionState::Reconnected));
+    }
+}
+
+/// Calls `attempt` with an increasing delay before every call until it returns a value
+async fn with_backoff<R, F: Future<Output = Option<R>>>(mut attempt: impl FnMut() -> F) -> R {
+    let mut delay = MIN_RECONNECT_DELAY;
+    loop {
+        sleep_until(Some(Instant::now() + delay)).await;
+        if let Some(result) = attempt().await {
+            return result;
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+fn republish<T: Clone + Send + 'static>(old: &Connection, new: &Connection, paths: &ItemPaths) -> BoxFuture<'static, zbus::Result<()>> {
+    let (old, new, paths) = (old.clone(), new.clone(), paths.clone());
+    Box::pin(async move {
+        let item = old
+            .object_server()
+            .interface::<_, StatusNotifierItem<T>>(&paths.item)
+            .await?
+            .get()
+            .await
+            .duplicate();
+        let menu = old
+            .object_server()
+            .interface::<_, DBusMenu<T>>(&paths.menu)
+            .await?
+            .get()
+            .await
+            .duplicate();
+        new.object_server().at(&paths.item, item).await?;
+        new.object_server().at(&paths.menu, menu).await?;
+        Ok(())
+    })
+}
+
+#[cfg(all(test, feature = "async-io"))]
+mod tests {
+    use std::cell::Cell;
+    use std::future::ready;
+
+    use super::*;
+
+    /// Runs outside of a tokio runtime, like the connection does by default even with the `tokio` feature
+    #[test]
+    fn backoff_retries_with_increasing_delay() {
+        let attempts = Cell::new(0);
+        let start = Instant::now();
+        let result = async_io::block_on(with_backoff(|| {
+            attempts.set(attempts.get() + 1);
+            ready((attempts.get() == 3).then_some("reconnected"))
+        }));
+        assert_eq!(result, "reconnected");
+        assert_eq!(attempts.get(), 3);
+        assert!(start.elapsed() >= MIN_RECONNECT_DELAY * 7);
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use parking_lot::Mutex;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::error::{TrayError, TrayResult};
+use crate::platform::linux::icon::remove_process_icons;
+
+/// Who takes care of `SIGINT` and `SIGTERM` at the moment
+///
+/// signal-hook never uninstalls its handlers, so the default action is emulated while neither a tray loop
+/// nor the icon cleanup handles the signals. This is only done for signals that had their default action
+/// before, handlers the application installed beforehand keep working on their own.
+struct SignalState {
+    idle: Arc<AtomicBool>,
+    /// The signals that neither the application nor anything else handled when the state was created
+    defaults: Vec<i32>,
+    loops: AtomicUsize,
+    cleanup: AtomicBool
+}
+
+static STATE: OnceLock<SignalState> = OnceLock::new();
+
+fn state() -> &'static SignalState {
+    STATE.get_or_init(|| {
+        let idle = Arc::new(AtomicBool::new(true));
+        let defaults: Vec<i32> = [SIGINT, SIGTERM]
+            .into_iter()
+            .filter(|&signal| has_default_action(signal))
+            .collect();
+        for &signal in &defaults {
+            signal_hook::flag::register_conditional_default(signal, idle.clone())
+                .map_or_else(|err| log::warn!("Failed to restore the default action of signal {signal}: {err}"), drop);
+        }
+        SignalState {
+            idle,
+            defaults,
+            loops: AtomicUsize::new(0),
+            cleanup: AtomicBool::new(false)
+        }
+    })
+}
+
+/// Whether nothing handles or ignores `signal` yet
+fn has_default_action(signal: i32) -> bool {
+    // SAFETY: A null action only queries the current action, which is written to a zeroed struct that we own
+    unsafe {
+        let mut current: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(signal, std::ptr::null(), &mut current) == 0 && current.sa_sigaction == libc::SIG_DFL
+    }
+}
+
+impl SignalState {
+    fn update_idle(&self) {
+        let idle = self.loops.load(Ordering::Acquire) == 0 && !self.cleanup.load(Ordering::Acquire);
+        self.idle.store(idle, Ordering::Release);
+    }
+}
+
+/// Lets a tray loop handle the signals until [leave_loop] is called
+///
+/// Must be called before the loop registers its own handlers, so the previous action of the signals is still known.
+pub fn enter_loop() {
+    let state = state();
+    state.loops.fetch_add(1, Ordering::AcqRel);
+    state.update_idle();
+}
+
+pub fn leave_loop() {
+    let state = state();
+    state.loops.fetch_sub(1, Ordering::AcqRel);
+    state.update_idle();
+}
+
+/// Deletes the icon files of the process before it gets terminated by `SIGINT` or `SIGTERM`
+///
+/// While a tray loop is running it stops on these signals and removes its tray by itself. A signal that the application
+/// handled before the first tray was created is left to that handler, which decides whether the process exits.
+pub fn enable_cleanup() -> TrayResult<()> {
+    static INSTALLED: Mutex<bool> = Mutex::new(false);
+
+    let mut installed = INSTALLED.lock();
+    if *installed {
+        return Ok(());
+    }
+    let state = state();
+    let mut signals = Signals::new([SIGINT, SIGTERM]).map_err(|err| TrayError::custom(format!("Failed to register signal handlers: {err}")))?;
+    std::thread::Builder::new()
+        .name(String::from("betrayer signal cleanup"))
+        .spawn(move || {
+            for signal in signals.forever() {
+                if state.loops.load(Ordering::Acquire) == 0 {
+                    log::debug!("Received signal {signal}, removing icon files");
+                    remove_process_icons();
+                    if state.defaults.contains(&signal) {
+                        signal_hook::low_level::emulate_default_handler(signal)
+                            .unwrap_or_else(|err| log::warn!("Failed to run the default action of signal {signal}: {err}"));
+                    }
+                }
+            }
+        })
+        .map_err(|err| TrayError::custom(format!("Failed to spawn signal thread: {err}")))?;
+    state.cleanup.store(true, Ordering::Release);
+    state.update_idle();
+    *installed = true;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, ExitStatus, Stdio};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::platform::linux::icon::{IconCache, NativeIcon};
+
+    const CHILD_DIR: &str = "BETRAYER_SIGNAL_TEST_DIR";
+
+    /// Runs `test` in a new process of the test binary, as the signals would terminate the test runner otherwise
+    ///
+    /// The child gets a directory for its icons, which is returned to the parent together with the exit status.
+    fn in_child(name: &str, test: impl FnOnce(&Path)) -> Option<(ExitStatus, tempfile::TempDir)> {
+        if let Some(dir) = std::env::var_os(CHILD_DIR) {
+            test(&PathBuf::from(dir));
+            return None;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", name, "--test-threads=1"])
+            .env(CHILD_DIR, dir.path())
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        Some((status, dir))
+    }
+
+    /// Writes an icon file for the current process and keeps it until the process ends
+    fn write_icon(dir: &Path) {
+        let mut cache = IconCache::new(Some(dir.to_path_buf()), (std::process::id(), 0), 1);
+        cache
+            .get(&NativeIcon::from_rgba(vec![255; 4], 1, 1).unwrap())
+            .unwrap();
+        std::mem::forget(cache);
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+    }
+
+    fn is_empty(dir: &Path) -> bool {
+        std::fs::read_dir(dir).unwrap().next().is_none()
+    }
+
+    #[test]
+    fn cleanup_removes_icons_and_terminates() {
+        let child = in_child("platform::linux::signals::tests::cleanup_removes_icons_and_terminates", |dir| {
+            write_icon(dir);
+            enable_cleanup().unwrap();
+            // SAFETY: Raising a signal has no preconditions
+            unsafe { libc::raise(SIGTERM) };
+            std::thread::sleep(Duration::from_secs(5));
+        });
+        if let Some((status, dir)) = child {
+            assert_eq!(status.signal(), Some(SIGTERM));
+            assert!(is_empty(dir.path()));
+        }
+    }
+
+    #[test]
+    fn cleanup_leaves_handled_signals_to_the_application() {
+        let child = in_child(
+            "platform::linux::signals::tests::cleanup_leaves_handled_signals_to_the_application",
+            |dir| {
+                let handled = Arc::new(AtomicBool::new(false));
+                signal_hook::flag::register(SIGTERM, handled.clone()).unwrap();
+                write_icon(dir);
+                enable_cleanup().unwrap();
+                // SAFETY: Raising a signal has no preconditions
+                unsafe { libc::raise(SIGTERM) };
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while !(handled.load(Ordering::Acquire) && is_empty(dir)) {
+                    assert!(Instant::now() < deadline, "timed out");
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+        );
+        if let Some((status, dir)) = child {
+            assert!(status.success(), "{status}");
+            assert!(is_empty(dir.path()));
+        }
+    }
+}
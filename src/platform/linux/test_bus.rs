@@ -0,0 +1,246 @@
+//! A private session bus with a fake tray host for the tests of the shared connection
+
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Once, OnceLock};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, MutexGuard};
+use zbus::message::Header;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::OwnedValue;
+use zbus::{connection, interface, Connection};
+
+static BUS: Mutex<Option<Daemon>> = Mutex::new(None);
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+static PID: AtomicI32 = AtomicI32::new(0);
+static STOP_AT_EXIT: Once = Once::new();
+
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+
+/// Exclusive access to the session bus of the test process
+///
+/// All trays of a process share a single executor and watcher subscription, so the tests that use the bus take turns.
+pub(crate) struct TestBus(MutexGuard<'static, Option<Daemon>>);
+
+struct Daemon {
+    child: Child,
+    address: String,
+    watcher: Connection,
+    state: Arc<WatcherState>
+}
+
+#[derive(Default)]
+struct WatcherState {
+    host: AtomicBool,
+    items: Mutex<Vec<(String, String)>>
+}
+
+/// Starts the bus on first use and sets it as the session bus of the process
+///
+/// Returns `None` when `dbus-daemon` is not installed, the calling test should be skipped then.
+pub(crate) fn session_bus() -> Option<TestBus> {
+    let mut bus = BUS.lock();
+    if bus.is_none() {
+        let Some(daemon) = Daemon::start() else {
+            eprintln!("dbus-daemon is not available, skipping test");
+            return None;
+        };
+        std::env::set_var("DBUS_SESSION_BUS_ADDRESS", &daemon.address);
+        // SAFETY: The handler only signals a process and removes a directory
+        STOP_AT_EXIT.call_once(|| unsafe {
+            libc::atexit(stop_daemon);
+        });
+        *bus = Some(daemon);
+    }
+    // A test that failed may have left the watcher in any state
+    let daemon = bus.as_ref().unwrap();
+    daemon.state.host.store(true, Ordering::Release);
+    daemon.state.items.lock().clear();
+    let bus = TestBus(bus);
+    if !bus.has_owner(WATCHER_NAME) {
+        bus.set_watcher(true);
+    }
+    Some(bus)
+}
+
+extern "C" fn stop_daemon() {
+    // SAFETY: Only signals the daemon that was started by this process
+    unsafe { libc::kill(PID.load(Ordering::Acquire), libc::SIGTERM) };
+    if let Some(dir) = DIR.get() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+impl Daemon {
+    /// Starts a daemon that always listens at the same address
+    fn start() -> Option<Self> {
+        let dir = DIR.get_or_init(|| tempfile::tempdir().unwrap().keep());
+        // Without the guid of the daemon, so the address stays valid for a restarted daemon
+        let address = format!("unix:path={}", dir.join("bus").display());
+        let mut child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .arg(format!("--address={address}"))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        PID.store(child.id() as i32, Ordering::Release);
+
+        let state = Arc::new(WatcherState {
+            host: AtomicBool::new(true),
+            ..Default::default()
+        });
+        let watcher = async_io::block_on(
+            connection::Builder::address(line.trim())
+                .unwrap()
+                .name(WATCHER_NAME)
+                .unwrap()
+                .serve_at(WATCHER_PATH, Watcher(state.clone()))
+                .unwrap()
+                .build()
+        )
+        .unwrap();
+        Some(Self {
+            child,
+            address,
+            watcher,
+            state
+        })
+    }
+}
+
+impl TestBus {
+    fn daemon(&self) -> &Daemon {
+        self.0.as_ref().unwrap()
+    }
+
+    /// The items that were registered with the watcher as `(service, sender)`
+    pub(crate) fn items(&self) -> Vec<(String, String)> {
+        self.daemon().state.items.lock().clone()
+    }
+
+    /// Shows or hides the fake host and tells the trays about it
+    pub(crate) fn set_host(&self, available: bool) {
+        let daemon = self.daemon();
+        daemon.state.host.store(available, Ordering::Release);
+        let emitter = SignalEmitter::new(&daemon.watcher, WATCHER_PATH).unwrap();
+        async_io::block_on(async {
+            match available {
+                true => Watcher::status_notifier_host_registered(&emitter).await,
+                false => Watcher::status_notifier_host_unregistered(&emitter).await
+            }
+        })
+        .unwrap();
+    }
+
+    /// Starts or stops the fake watcher by taking or releasing its name
+    pub(crate) fn set_watcher(&self, running: bool) {
+        let connection = &self.daemon().watcher;
+        async_io::block_on(async {
+            match running {
+                true => connection.request_name(WATCHER_NAME).await,
+                false => connection.release_name(WATCHER_NAME).await.map(drop)
+            }
+        })
+        .unwrap();
+    }
+
+    /// Stops the daemon, which closes all connections to it
+    pub(crate) fn stop(&mut self) {
+        let mut daemon = self.0.take().unwrap();
+        // SAFETY: Only signals the daemon that was started by this process
+        unsafe { libc::kill(daemon.child.id() as i32, libc::SIGTERM) };
+        daemon.child.wait().unwrap();
+    }
+
+    /// Starts a new daemon at the address of the stopped one
+    pub(crate) fn start(&mut self) {
+        *self.0 = Some(Daemon::start().expect("failed to restart dbus-daemon"));
+    }
+
+    /// Reads a property of the tray item of `service` at `path`
+    pub(crate) fn item_property(&self, service: &str, path: &str, name: &str) -> zbus::Result<OwnedValue> {
+        let connection = &self.daemon().watcher;
+        let reply = async_io::block_on(connection.call_method(
+            Some(service),
+            path,
+            Some("org.freedesktop.DBus.Properties"),
+            "Get",
+            &("org.kde.StatusNotifierItem", name)
+        ))?;
+        reply.body().deserialize()
+    }
+
+    /// Whether `service` has an object with a tray item at `path`
+    pub(crate) fn has_item(&self, service: &str, path: &str) -> bool {
+        self.item_property(service, path, "Status").is_ok()
+    }
+
+    /// Whether anyone owns `name` on the bus
+    pub(crate) fn has_owner(&self, name: &str) -> bool {
+        let connection = &self.daemon().watcher;
+        async_io::block_on(async {
+            zbus::fdo::DBusProxy::new(connection)
+                .await
+                .unwrap()
+                .name_has_owner(name.try_into().unwrap())
+                .await
+                .unwrap()
+        })
+    }
+}
+
+/// Polls `condition` for up to five seconds
+pub(crate) fn wait_until(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+struct Watcher(Arc<WatcherState>);
+
+#[interface(name = "org.kde.StatusNotifierWatcher")]
+impl Watcher {
+    fn register_status_notifier_item(&self, service: &str, #[zbus(header)] header: Header<'_>) {
+        let sender = header.sender().map(ToString::to_string).unwrap_or_default();
+        self.0.items.lock().push((service.to_owned(), sender));
+    }
+
+    fn register_status_notifier_host(&self, _service: &str) {}
+
+    #[zbus(property)]
+    fn is_status_notifier_host_registered(&self) -> bool {
+        self.0.host.load(Ordering::Acquire)
+    }
+
+    #[zbus(property)]
+    fn protocol_version(&self) -> i32 {
+        0
+    }
+
+    #[zbus(property)]
+    fn registered_status_notifier_items(&self) -> Vec<String> {
+        self.0
+            .items
+            .lock()
+            .iter()
+            .map(|(service, _)| service.clone())
+            .collect()
+    }
+
+    #[zbus(signal)]
+    async fn status_notifier_host_registered(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn status_notifier_host_unregistered(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
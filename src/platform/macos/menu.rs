@@ -4,10 +4,13 @@ use objc2_app_kit::{NSControlStateValueOff, NSControlStateValueOn, NSMenu, NSMen
 use objc2_foundation::{MainThreadMarker, NSString};
 
 use crate::platform::macos::callback::SystemTrayCallback;
-use crate::{Menu, MenuItem};
+use crate::{Menu, MenuItem, MenuSignal};
+
+/// A signal together with the menu item of check buttons, which is needed to flip their checkmark
+pub type SignalEntry<T> = (MenuSignal<T>, Option<Id<NSMenuItem>>);
 
 pub unsafe fn build_menu_item<T>(
-    marker: MainThreadMarker, item: MenuItem<T>, callback: &SystemTrayCallback, signal_map: &mut Vec<T>
+    marker: MainThreadMarker, item: MenuItem<T>, callback: &SystemTrayCallback, signal_map: &mut Vec<SignalEntry<T>>
 ) -> Id<NSMenuItem> {
     match item {
         MenuItem::Separator => NSMenuItem::separatorItem(marker),
@@ -27,7 +30,16 @@ pub unsafe fn build_menu_item<T>(
             button.setTarget(Some(callback));
             button.setAction(Some(SystemTrayCallback::selector()));
             button.setTag(signal_map.len() as NSInteger);
-            signal_map.push(signal);
+            signal_map.push((MenuSignal::Signal(signal), checked.map(|_| button.clone())));
+            button
+        }
+        MenuItem::Action { name, action } => {
+            let button = NSMenuItem::new(marker);
+            button.setTitle(&NSString::from_str(&name));
+            button.setTarget(Some(callback));
+            button.setAction(Some(SystemTrayCallback::selector()));
+            button.setTag(signal_map.len() as NSInteger);
+            signal_map.push((MenuSignal::Action(action), None));
             button
         }
         MenuItem::Menu { name, children } => {
@@ -49,7 +61,7 @@ pub unsafe fn build_menu_item<T>(
     }
 }
 
-pub fn construct_native_menu<T>(marker: MainThreadMarker, menu: Menu<T>, callback: &SystemTrayCallback) -> (Id<NSMenu>, Vec<T>) {
+pub fn construct_native_menu<T>(marker: MainThreadMarker, menu: Menu<T>, callback: &SystemTrayCallback) -> (Id<NSMenu>, Vec<SignalEntry<T>>) {
     unsafe {
         let mut signal_map = Vec::new();
         let native_menu = NSMenu::new(marker);
@@ -59,3 +71,20 @@ pub fn construct_native_menu<T>(marker: MainThreadMarker, menu: Menu<T>, callbac
         (native_menu, signal_map)
     }
 }
+
+/// Flips the checkmark of a check button and returns the new state
+pub fn toggle_menu_item(item: &NSMenuItem) -> bool {
+    unsafe {
+        let checked = item.state() != NSControlStateValueOn;
+        item.setState(match checked {
+            true => NSControlStateValueOn,
+            false => NSControlStateValueOff
+        });
+        checked
+    }
+}
+
+/// Whether a check button currently shows its checkmark
+pub fn is_checked(item: &NSMenuItem) -> bool {
+    unsafe { item.state() == NSControlStateValueOn }
+}
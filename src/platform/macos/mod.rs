@@ -2,22 +2,27 @@ mod callback;
 mod menu;
 
 use std::cell::{Cell, RefCell};
+use std::future::Future;
 use std::rc::Rc;
 
 use objc2::rc::Id;
 use objc2_app_kit::{NSApplication, NSStatusBar, NSStatusItem, NSVariableStatusItemLength};
 use objc2_foundation::{MainThreadMarker, NSString};
 
-use crate::error::TrayResult;
+use crate::error::{TrayErrorKind, TrayResult};
+use crate::platform::layout::MenuLayout;
 use crate::platform::macos::callback::SystemTrayCallback;
-use crate::platform::macos::menu::construct_native_menu;
-use crate::utils::OptionCellExt;
-use crate::{ClickType, Icon, Menu, TrayError, TrayEvent, TrayIconBuilder};
+use crate::platform::macos::menu::{construct_native_menu, is_checked, toggle_menu_item, SignalEntry};
+use crate::run::RunLoop;
+use crate::utils::{spawn_thread, OptionCellExt};
+use crate::{ClickType, Icon, Menu, SnapshotItem, TrayChanges, TrayError, TrayEvent, TrayIconBuilder, TraySnapshot, TrayStatus};
 
 pub struct NativeTrayIcon<T> {
     marker: MainThreadMarker,
     status_item: Id<NSStatusItem>,
-    signal_map: Rc<Cell<Option<Vec<T>>>>,
+    signal_map: Rc<Cell<Option<Vec<SignalEntry<T>>>>>,
+    layout: RefCell<Option<Vec<MenuLayout>>>,
+    tooltip: RefCell<Option<String>>,
     callback: Id<SystemTrayCallback>
 }
 
@@ -39,17 +44,24 @@ impl<T: Clone + 'static> NativeTrayIcon<T> {
             let callback = {
                 let signal_map = signal_map.clone();
                 let callback = RefCell::new(callback);
+                let auto_toggle = builder.auto_toggle;
                 SystemTrayCallback::new(move |tag| {
                     if tag == -1 {
                         callback.borrow_mut()(TrayEvent::Tray(ClickType::Left));
                     } else {
                         let signal = signal_map
-                            .with(|map: &mut Vec<T>| map.get(tag as usize).cloned())
+                            .with(|map: &mut Vec<SignalEntry<T>>| {
+                                map.get(tag as usize)
+                                    .map(|(signal, item)| match item.as_deref().filter(|_| auto_toggle) {
+                                        Some(item) => (signal.clone(), Some(toggle_menu_item(item))),
+                                        None => (signal.clone(), None)
+                                    })
+                            })
                             .flatten();
-                        if let Some(signal) = signal {
-                            callback.borrow_mut()(TrayEvent::Menu(signal));
-                        } else {
-                            log::debug!("Failed to get signal for tag {}", tag);
+                        match signal {
+                            Some((signal, Some(checked))) => signal.dispatch(|signal| callback.borrow_mut()(TrayEvent::MenuToggled(signal, checked))),
+                            Some((signal, None)) => signal.dispatch(|signal| callback.borrow_mut()(TrayEvent::Menu(signal))),
+                            None => log::debug!("Failed to get signal for tag {}", tag)
                         }
                     }
                 })
@@ -61,6 +73,7 @@ impl<T: Clone + 'static> NativeTrayIcon<T> {
                 button.setTag(-1);
             }
 
+            let layout = builder.menu.as_ref().map(MenuLayout::of);
             if let Some((menu, map)) = builder
                 .menu
                 .map(|menu| construct_native_menu(marker, menu, &callback))
@@ -73,10 +86,19 @@ impl<T: Clone + 'static> NativeTrayIcon<T> {
                 marker,
                 status_item,
                 signal_map,
+                layout: RefCell::new(layout),
+                tooltip: RefCell::new(builder.tooltip),
                 callback
             })
         }
     }
+
+    pub async fn new_async<F>(builder: TrayIconBuilder<T>, callback: F) -> TrayResult<Self>
+    where
+        F: FnMut(TrayEvent<T>) + Send + 'static
+    {
+        Self::new(builder, callback)
+    }
 }
 
 impl<T> Drop for NativeTrayIcon<T> {
@@ -92,22 +114,108 @@ impl<T> Drop for NativeTrayIcon<T> {
 }
 
 impl<T> NativeTrayIcon<T> {
-    pub fn set_tooltip(&self, _tooltip: Option<String>) {}
+    pub async fn shutdown(self) -> TrayResult<()> {
+        Ok(())
+    }
+
+    pub fn close(self) -> TrayResult<()> {
+        Ok(())
+    }
+
+    pub fn spawn_handler<F: Future<Output = ()> + Send + 'static>(&mut self, handler: F) -> TrayResult<()> {
+        spawn_thread("betrayer event handler", handler)
+    }
+
+    pub fn tooltip(&self) -> Option<String> {
+        self.tooltip.borrow().clone()
+    }
+
+    /// The user can remove status items from the menu bar, AppKit keeps them around as invisible items then
+    pub fn status(&self) -> TrayStatus {
+        match unsafe { self.status_item.isVisible() } {
+            true => TrayStatus::Visible,
+            false => TrayStatus::NoHost
+        }
+    }
+
+    pub fn set_tooltip(&self, tooltip: Option<String>) -> TrayResult<()> {
+        *self.tooltip.borrow_mut() = tooltip;
+        Ok(())
+    }
+
+    pub fn set_icon(&self, _icon: Option<Icon>) -> TrayResult<()> {
+        Ok(())
+    }
+
+    pub async fn set_tooltip_async(&self, tooltip: Option<String>) -> TrayResult<()> {
+        self.set_tooltip(tooltip)
+    }
 
-    pub fn set_icon(&self, _icon: Option<Icon>) {}
+    pub async fn set_icon_async(&self, icon: Option<Icon>) -> TrayResult<()> {
+        self.set_icon(icon)
+    }
+
+    pub async fn set_menu_async(&self, menu: Option<Menu<T>>) -> TrayResult<()> {
+        self.set_menu(menu)
+    }
 
-    pub fn set_menu(&self, menu: Option<Menu<T>>) {
+    pub fn set_menu(&self, menu: Option<Menu<T>>) -> TrayResult<()> {
         match menu {
             None => {
                 unsafe { self.status_item.setMenu(None) };
                 self.signal_map.set(None);
+                *self.layout.borrow_mut() = None;
             }
             Some(menu) => {
+                let layout = MenuLayout::of(&menu);
                 let (menu, signals) = construct_native_menu(self.marker, menu, &self.callback);
                 unsafe { self.status_item.setMenu(Some(&menu)) };
                 self.signal_map.set(Some(signals));
+                *self.layout.borrow_mut() = Some(layout);
             }
         }
+        Ok(())
+    }
+
+    pub async fn update_async(&self, changes: TrayChanges<T>) -> TrayResult<()> {
+        self.update(changes)
+    }
+
+    pub fn update(&self, changes: TrayChanges<T>) -> TrayResult<()> {
+        if let Some(tooltip) = changes.tooltip {
+            self.set_tooltip(tooltip)?;
+        }
+        if let Some(menu) = changes.menu {
+            self.set_menu(menu)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Clone + 'static> NativeTrayIcon<T> {
+    pub fn menu(&self) -> Option<Menu<T>> {
+        let layout = self.layout.borrow();
+        let layout = layout.as_ref()?;
+        self.signal_map.with(|signals: &mut Vec<SignalEntry<T>>| {
+            let entry = |id: usize| {
+                signals
+                    .get(id)
+                    .map(|(signal, item)| (signal.clone(), item.as_deref().map(is_checked)))
+            };
+            Menu::new(MenuLayout::rebuild(layout, &entry))
+        })
+    }
+
+    pub fn snapshot(&self) -> TraySnapshot<T> {
+        TraySnapshot {
+            tooltip: self.tooltip(),
+            // Icons are not supported yet
+            icon: None,
+            menu: self
+                .menu()
+                .map(|menu| menu.into_iter().map(SnapshotItem::from).collect()),
+            status: self.status()
+        }
     }
 }
 
@@ -121,3 +229,14 @@ impl NativeIcon {
 }
 
 pub type PlatformError = ();
+
+pub fn os_error_kind(_err: &PlatformError) -> TrayErrorKind {
+    TrayErrorKind::Other
+}
+
+pub fn run<T: Clone + Send + 'static>(_builder: TrayIconBuilder<T>, _state: RunLoop<T>) -> TrayResult<()> {
+    Err(TrayError::new(
+        TrayErrorKind::Unsupported,
+        "The standalone tray loop is not supported on MacOS yet"
+    ))
+}
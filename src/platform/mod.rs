@@ -1,3 +1,5 @@
+#[cfg(not(target_os = "linux"))]
+mod layout;
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "macos")]
@@ -5,9 +7,11 @@ mod macos;
 #[cfg(target_os = "windows")]
 mod windows;
 
+#[cfg(all(test, target_os = "linux", feature = "async-io"))]
+pub(crate) use linux::tests;
 #[cfg(target_os = "linux")]
-pub use linux::{NativeIcon, NativeTrayIcon, PlatformError};
+pub use linux::{os_error_kind, probe, probe_async, run, NativeIcon, NativeTrayHandle, NativeTrayIcon, PlatformError};
 #[cfg(target_os = "macos")]
-pub use macos::{NativeIcon, NativeTrayIcon, PlatformError};
+pub use macos::{os_error_kind, run, NativeIcon, NativeTrayIcon, PlatformError};
 #[cfg(target_os = "windows")]
-pub use windows::{NativeIcon, NativeTrayIcon, PlatformError};
+pub use windows::{os_error_kind, run, NativeIcon, NativeTrayIcon, PlatformError};
@@ -1,4 +1,5 @@
 use std::fmt::{Debug, Formatter};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::ptr::null_mut;
 use std::sync::Arc;
 
@@ -6,14 +7,24 @@ use windows_sys::Win32::UI::WindowsAndMessaging::{CreateIcon, DestroyIcon, LoadI
 
 use crate::error::TrayResult;
 use crate::platform::windows::{error_check, get_instance_handle};
+use crate::SnapshotIcon;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct NativeIcon {
-    handle: Arc<NativeIconHandle>
+    handle: Arc<NativeIconHandle>,
+    description: SnapshotIcon
 }
 
 impl NativeIcon {
     pub fn from_rgba(mut rgba: Vec<u8>, width: u32, height: u32) -> TrayResult<Self> {
+        let description = SnapshotIcon::Image {
+            hash: {
+                let mut hasher = DefaultHasher::new();
+                rgba.hash(&mut hasher);
+                hasher.finish()
+            },
+            size: Some((width, height))
+        };
         let mut mask = Vec::with_capacity(rgba.len() / 4);
         let bgra = {
             rgba.chunks_exact_mut(4).for_each(|pixel| {
@@ -36,7 +47,8 @@ impl NativeIcon {
             )
         })?;
         Ok(Self {
-            handle: Arc::new(NativeIconHandle(handle))
+            handle: Arc::new(NativeIconHandle(handle)),
+            description
         })
     }
 
@@ -55,13 +67,21 @@ impl NativeIcon {
             )
         })?;
         Ok(Self {
-            handle: Arc::new(NativeIconHandle(handle))
+            handle: Arc::new(NativeIconHandle(handle)),
+            description: SnapshotIcon::Named {
+                name: format!("#{resource_id}")
+            }
         })
     }
 
     pub fn handle(&self) -> HICON {
         self.handle.0
     }
+
+    /// How the icon shows up in a snapshot of the tray
+    pub fn describe(&self) -> SnapshotIcon {
+        self.description.clone()
+    }
 }
 
 #[derive(Eq, PartialEq)]
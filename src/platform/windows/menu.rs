@@ -4,17 +4,20 @@ use std::ptr::null_mut;
 
 use windows_sys::Win32::Foundation::HWND;
 use windows_sys::Win32::UI::WindowsAndMessaging::{
-    AppendMenuW, CreatePopupMenu, DestroyMenu, GetCursorPos, SetForegroundWindow, TrackPopupMenu, HMENU, MF_CHECKED, MF_POPUP, MF_SEPARATOR,
-    MF_STRING, TPM_BOTTOMALIGN, TPM_LEFTALIGN
+    AppendMenuW, CheckMenuItem, CreatePopupMenu, DestroyMenu, GetCursorPos, SetForegroundWindow, TrackPopupMenu, HMENU, MF_BYCOMMAND, MF_CHECKED,
+    MF_POPUP, MF_SEPARATOR, MF_STRING, MF_UNCHECKED, TPM_BOTTOMALIGN, TPM_LEFTALIGN
 };
 
 use crate::error::{TrayError, TrayResult};
+use crate::platform::layout::MenuLayout;
 use crate::platform::windows::{encode_wide, error_check};
-use crate::{Menu, MenuItem};
+use crate::{Menu, MenuItem, MenuSignal};
 
 pub struct NativeMenu {
     hmenu: HMENU,
-    signals_map: Box<dyn SignalMap>
+    signals_map: Box<dyn SignalMap>,
+    check_states: Vec<Option<bool>>,
+    layout: Vec<MenuLayout>
 }
 
 impl NativeMenu {
@@ -36,9 +39,28 @@ impl NativeMenu {
         Ok(())
     }
 
-    pub fn map(&self, id: u16) -> Option<&dyn Any> {
+    pub fn map(&self, id: u16) -> Option<MenuSignal<&dyn Any>> {
         self.signals_map.map(id)
     }
+
+    /// Flips the checkmark of a check button and returns the new state
+    pub fn toggle(&mut self, id: u16) -> Option<bool> {
+        let checked = self.check_states.get_mut(id as usize)?.as_mut()?;
+        *checked = !*checked;
+        let flag = if *checked { MF_CHECKED } else { MF_UNCHECKED };
+        unsafe { CheckMenuItem(self.hmenu, id as u32, MF_BYCOMMAND | flag) };
+        Some(*checked)
+    }
+
+    /// The menu this was built from, with the current check states
+    pub fn menu<T: Clone + 'static>(&self) -> Option<Menu<T>> {
+        let signals = self
+            .signals_map
+            .as_any()
+            .downcast_ref::<Vec<MenuSignal<T>>>()?;
+        let entry = |id: usize| Some((signals.get(id)?.clone(), self.check_states.get(id).copied().flatten()));
+        Some(Menu::new(MenuLayout::rebuild(&self.layout, &entry)))
+    }
 }
 
 impl Drop for NativeMenu {
@@ -50,23 +72,31 @@ impl Drop for NativeMenu {
     }
 }
 
-fn add_all<T>(hmenu: HMENU, signals: &mut Vec<T>, items: Vec<MenuItem<T>>) -> TrayResult<()> {
+fn add_all<T>(hmenu: HMENU, signals: &mut Vec<MenuSignal<T>>, check_states: &mut Vec<Option<bool>>, items: Vec<MenuItem<T>>) -> TrayResult<()> {
     for item in items {
         match item {
             MenuItem::Separator => {
                 error_check(unsafe { AppendMenuW(hmenu, MF_SEPARATOR, 0, null_mut()) })?;
             }
             MenuItem::Button { name, signal, checked } => {
-                let checked = checked
-                    .map(|v| v.then_some(MF_CHECKED).unwrap_or_default())
-                    .unwrap_or_default();
+                let flags = match checked {
+                    Some(true) => MF_CHECKED,
+                    _ => MF_UNCHECKED
+                };
+                let wide = encode_wide(&name);
+                error_check(unsafe { AppendMenuW(hmenu, MF_STRING | flags, signals.len(), wide.as_ptr()) })?;
+                signals.push(MenuSignal::Signal(signal));
+                check_states.push(checked);
+            }
+            MenuItem::Action { name, action } => {
                 let wide = encode_wide(&name);
-                error_check(unsafe { AppendMenuW(hmenu, MF_STRING | checked, signals.len(), wide.as_ptr()) })?;
-                signals.push(signal);
+                error_check(unsafe { AppendMenuW(hmenu, MF_STRING, signals.len(), wide.as_ptr()) })?;
+                signals.push(MenuSignal::Action(action));
+                check_states.push(None);
             }
             MenuItem::Menu { name, children } => {
                 let submenu = error_check(unsafe { CreatePopupMenu() })?;
-                add_all(submenu, signals, children)?;
+                add_all(submenu, signals, check_states, children)?;
                 let wide = encode_wide(&name);
                 error_check(unsafe { AppendMenuW(hmenu, MF_POPUP, submenu as _, wide.as_ptr()) })?;
             }
@@ -80,22 +110,35 @@ impl<T: 'static> TryFrom<Menu<T>> for NativeMenu {
 
     fn try_from(value: Menu<T>) -> Result<Self, Self::Error> {
         log::trace!("Creating new native menu");
+        let layout = MenuLayout::of(&value);
         let hmenu = error_check(unsafe { CreatePopupMenu() })?;
-        let mut signals = Vec::<T>::new();
-        add_all(hmenu, &mut signals, value.items)?;
+        let mut signals = Vec::<MenuSignal<T>>::new();
+        let mut check_states = Vec::new();
+        add_all(hmenu, &mut signals, &mut check_states, value.items)?;
         Ok(Self {
             hmenu,
-            signals_map: Box::new(signals)
+            signals_map: Box::new(signals),
+            check_states,
+            layout
         })
     }
 }
 
 trait SignalMap {
-    fn map(&self, id: u16) -> Option<&dyn Any>;
+    fn map(&self, id: u16) -> Option<MenuSignal<&dyn Any>>;
+
+    fn as_any(&self) -> &dyn Any;
 }
 
-impl<T: 'static> SignalMap for Vec<T> {
-    fn map(&self, id: u16) -> Option<&dyn Any> {
-        self.get(id as usize).map(|r| r as _)
+impl<T: 'static> SignalMap for Vec<MenuSignal<T>> {
+    fn map(&self, id: u16) -> Option<MenuSignal<&dyn Any>> {
+        self.get(id as usize).map(|r| match r {
+            MenuSignal::Signal(signal) => MenuSignal::Signal(signal as _),
+            MenuSignal::Action(action) => MenuSignal::Action(action.clone())
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
@@ -1,9 +1,11 @@
 mod icon;
 mod menu;
+mod run;
 mod tray;
 
 use std::any::Any;
 use std::cell::Cell;
+use std::future::Future;
 use std::iter::once;
 use std::marker::PhantomData;
 use std::mem::zeroed;
@@ -13,6 +15,7 @@ use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{LazyLock, Once};
 
 pub use icon::NativeIcon;
+pub use run::run;
 use windows_sys::core::{w, PCWSTR};
 use windows_sys::Win32::Foundation::{BOOL, HINSTANCE, HWND, LPARAM, LRESULT, WPARAM};
 use windows_sys::Win32::System::SystemServices::IMAGE_DOS_HEADER;
@@ -22,11 +25,11 @@ use windows_sys::Win32::UI::WindowsAndMessaging::{
     WM_LBUTTONUP, WM_RBUTTONUP, WNDCLASSW, WS_EX_LAYERED, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_EX_TRANSPARENT
 };
 
-use crate::error::{ErrorSource, TrayResult};
+use crate::error::{ErrorSource, TrayErrorKind, TrayResult};
 use crate::platform::windows::menu::NativeMenu;
 use crate::platform::windows::tray::{DataAction, TrayIconData};
-use crate::utils::OptionCellExt;
-use crate::{ClickType, Icon, Menu, TrayEvent, TrayIconBuilder};
+use crate::utils::{spawn_thread, OptionCellExt};
+use crate::{ClickType, Icon, Menu, SnapshotItem, TrayChanges, TrayEvent, TrayIconBuilder, TraySnapshot, TrayStatus};
 
 //TODO Better error handling for the set_* functions
 //TODO Replace Cell to avoid potential overrides
@@ -38,11 +41,13 @@ pub struct NativeTrayIcon<T> {
     hwnd: HWND,
     tray_id: u32,
     shared: Rc<SharedTrayData>,
+    removed: bool,
     _signal_type: PhantomData<T>
 }
 
 struct TrayLoopData {
     tray_id: u32,
+    auto_toggle: bool,
     shared: Rc<SharedTrayData>,
     #[allow(clippy::type_complexity)]
     callback: Box<dyn FnMut(TrayEvent<&dyn Any>) + 'static>
@@ -51,7 +56,9 @@ struct TrayLoopData {
 struct SharedTrayData {
     menu: Cell<Option<NativeMenu>>,
     tooltip: Cell<Option<String>>,
-    icon: Cell<Option<NativeIcon>>
+    icon: Cell<Option<NativeIcon>>,
+    /// Whether the taskbar took the icon, which it forgets when Explorer restarts
+    registered: Cell<bool>
 }
 
 impl<T: Clone + 'static> NativeTrayIcon<T> {
@@ -81,25 +88,27 @@ impl<T: Clone + 'static> NativeTrayIcon<T> {
         let shared = Rc::new(SharedTrayData {
             menu: Cell::new(builder.menu.map(NativeMenu::try_from).transpose()?),
             tooltip: Cell::new(builder.tooltip),
-            icon: Cell::new(builder.icon.map(NativeIcon::from))
+            icon: Cell::new(builder.icon.map(NativeIcon::from)),
+            registered: Cell::new(false)
         });
 
         TrayIconData::from(&shared)
             .with_message(WM_USER_TRAY_ICON)
             .apply(hwnd, tray_id, DataAction::Add)?;
+        shared.registered.set(true);
 
         let data = TrayLoopData {
             tray_id,
+            auto_toggle: builder.auto_toggle,
             shared: shared.clone(),
             callback: Box::new(move |event: TrayEvent<&dyn Any>| {
                 let event = match event {
-                    TrayEvent::Menu(signal) => TrayEvent::Menu(
-                        signal
-                            .downcast_ref::<T>()
-                            .expect("Signal has the wrong type")
-                            .clone()
-                    ),
-                    TrayEvent::Tray(click) => TrayEvent::Tray(click)
+                    TrayEvent::Menu(signal) => TrayEvent::Menu(downcast_signal::<T>(signal)),
+                    TrayEvent::MenuToggled(signal, checked) => TrayEvent::MenuToggled(downcast_signal::<T>(signal), checked),
+                    TrayEvent::Tray(click) => TrayEvent::Tray(click),
+                    TrayEvent::Connection(state) => TrayEvent::Connection(state),
+                    TrayEvent::HostAvailable => TrayEvent::HostAvailable,
+                    TrayEvent::HostUnavailable => TrayEvent::HostUnavailable
                 };
                 callback(event);
             })
@@ -111,33 +120,126 @@ impl<T: Clone + 'static> NativeTrayIcon<T> {
             hwnd,
             tray_id,
             shared,
+            removed: false,
             _signal_type: PhantomData
         })
     }
+
+    pub async fn new_async<F>(builder: TrayIconBuilder<T>, callback: F) -> TrayResult<Self>
+    where
+        F: FnMut(TrayEvent<T>) + Send + 'static
+    {
+        Self::new(builder, callback)
+    }
 }
 
 impl<T> NativeTrayIcon<T> {
-    pub fn set_tooltip(&self, tooltip: Option<String>) {
+    pub async fn shutdown(self) -> TrayResult<()> {
+        self.close()
+    }
+
+    pub fn close(mut self) -> TrayResult<()> {
+        self.removed = true;
+        TrayIconData::default().apply(self.hwnd, self.tray_id, DataAction::Remove)
+    }
+
+    pub fn spawn_handler<F: Future<Output = ()> + Send + 'static>(&mut self, handler: F) -> TrayResult<()> {
+        spawn_thread("betrayer event handler", handler)
+    }
+
+    pub async fn set_tooltip_async(&self, tooltip: Option<String>) -> TrayResult<()> {
+        self.set_tooltip(tooltip)
+    }
+
+    pub async fn set_icon_async(&self, icon: Option<Icon>) -> TrayResult<()> {
+        self.set_icon(icon)
+    }
+
+    pub fn tooltip(&self) -> Option<String> {
+        self.shared.tooltip.with(|tooltip| tooltip.clone())
+    }
+
+    pub fn status(&self) -> TrayStatus {
+        match self.shared.registered.get() {
+            true => TrayStatus::Visible,
+            false => TrayStatus::NoHost
+        }
+    }
+
+    pub fn set_tooltip(&self, tooltip: Option<String>) -> TrayResult<()> {
         TrayIconData::default()
             .with_tooltip(tooltip.as_deref().unwrap_or(""))
-            .apply(self.hwnd, self.tray_id, DataAction::Modify)
-            .unwrap();
-        self.shared.tooltip.set(tooltip)
+            .apply(self.hwnd, self.tray_id, DataAction::Modify)?;
+        self.shared.tooltip.set(tooltip);
+        Ok(())
     }
 
-    pub fn set_icon(&self, icon: Option<Icon>) {
+    pub fn set_icon(&self, icon: Option<Icon>) -> TrayResult<()> {
         TrayIconData::default()
             .with_icon(icon.as_ref().map(|i| i.0.handle()).unwrap_or(null_mut()))
-            .apply(self.hwnd, self.tray_id, DataAction::Modify)
-            .unwrap();
-        self.shared.icon.set(icon.map(|i| i.0))
+            .apply(self.hwnd, self.tray_id, DataAction::Modify)?;
+        self.shared.icon.set(icon.map(|i| i.0));
+        Ok(())
     }
 }
 
 impl<T: 'static> NativeTrayIcon<T> {
-    pub fn set_menu(&self, menu: Option<Menu<T>>) {
-        let menu = menu.map(|m| NativeMenu::try_from(m).unwrap());
+    pub async fn set_menu_async(&self, menu: Option<Menu<T>>) -> TrayResult<()> {
+        self.set_menu(menu)
+    }
+
+    pub fn set_menu(&self, menu: Option<Menu<T>>) -> TrayResult<()> {
+        let menu = menu.map(NativeMenu::try_from).transpose()?;
         self.shared.menu.set(menu);
+        Ok(())
+    }
+
+    pub async fn update_async(&self, changes: TrayChanges<T>) -> TrayResult<()> {
+        self.update(changes)
+    }
+
+    pub fn update(&self, changes: TrayChanges<T>) -> TrayResult<()> {
+        let menu = changes
+            .menu
+            .map(|menu| menu.map(NativeMenu::try_from).transpose())
+            .transpose()?;
+        if changes.tooltip.is_some() || changes.icon.is_some() {
+            let mut data = TrayIconData::default();
+            if let Some(tooltip) = &changes.tooltip {
+                data = data.with_tooltip(tooltip.as_deref().unwrap_or(""));
+            }
+            if let Some(icon) = &changes.icon {
+                data = data.with_icon(icon.as_ref().map(|i| i.0.handle()).unwrap_or(null_mut()));
+            }
+            data.apply(self.hwnd, self.tray_id, DataAction::Modify)?;
+        }
+        if let Some(tooltip) = changes.tooltip {
+            self.shared.tooltip.set(tooltip);
+        }
+        if let Some(icon) = changes.icon {
+            self.shared.icon.set(icon.map(|i| i.0));
+        }
+        if let Some(menu) = menu {
+            self.shared.menu.set(menu);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Clone + 'static> NativeTrayIcon<T> {
+    pub fn menu(&self) -> Option<Menu<T>> {
+        self.shared.menu.with(|menu| menu.menu()).flatten()
+    }
+
+    pub fn snapshot(&self) -> TraySnapshot<T> {
+        TraySnapshot {
+            tooltip: self.tooltip(),
+            icon: self.shared.icon.with(|icon| icon.describe()),
+            menu: self
+                .menu()
+                .map(|menu| menu.into_iter().map(SnapshotItem::from).collect()),
+            status: self.status()
+        }
     }
 }
 
@@ -145,9 +247,11 @@ impl<T> Drop for NativeTrayIcon<T> {
     fn drop(&mut self) {
         log::trace!("Destroying message window (tray id: {})", self.tray_id);
 
-        TrayIconData::default()
-            .apply(self.hwnd, self.tray_id, DataAction::Remove)
-            .unwrap_or_else(|err| log::warn!("Failed to remove tray icon: {err}"));
+        if !self.removed {
+            TrayIconData::default()
+                .apply(self.hwnd, self.tray_id, DataAction::Remove)
+                .unwrap_or_else(|err| log::warn!("Failed to remove tray icon: {err}"));
+        }
 
         if let Err(err) = error_check(unsafe { DestroyWindow(self.hwnd) }) {
             log::warn!("Failed to destroy message window: {err}")
@@ -165,10 +269,11 @@ unsafe extern "system" fn tray_subclass_proc(hwnd: HWND, msg: u32, wparam: WPARA
         }
         _ if msg == *S_U_TASKBAR_RESTART => {
             log::trace!("Taskbar restarted. Re-adding tray icon");
-            TrayIconData::from(&subclass_input.shared)
+            let result = TrayIconData::from(&subclass_input.shared)
                 .with_message(WM_USER_TRAY_ICON)
-                .apply(hwnd, subclass_input.tray_id, DataAction::Add)
-                .unwrap_or_else(|err| log::warn!("Failed to re-add tray icon: {err}"));
+                .apply(hwnd, subclass_input.tray_id, DataAction::Add);
+            subclass_input.shared.registered.set(result.is_ok());
+            result.unwrap_or_else(|err| log::warn!("Failed to re-add tray icon: {err}"));
         }
         WM_USER_TRAY_ICON => {
             if let Some(click) = ClickType::from_lparam(lparam) {
@@ -183,9 +288,16 @@ unsafe extern "system" fn tray_subclass_proc(hwnd: HWND, msg: u32, wparam: WPARA
         }
         WM_COMMAND => {
             let id = LOWORD(wparam as _);
-            subclass_input.shared.menu.with(|menu| match menu.map(id) {
-                None => log::debug!("Unknown menu item id: {id}"),
-                Some(signal) => (subclass_input.callback)(TrayEvent::Menu(signal))
+            subclass_input.shared.menu.with(|menu| {
+                let toggled = match subclass_input.auto_toggle {
+                    true => menu.toggle(id),
+                    false => None
+                };
+                match (menu.map(id), toggled) {
+                    (None, _) => log::debug!("Unknown menu item id: {id}"),
+                    (Some(signal), Some(checked)) => signal.dispatch(|signal| (subclass_input.callback)(TrayEvent::MenuToggled(signal, checked))),
+                    (Some(signal), None) => signal.dispatch(|signal| (subclass_input.callback)(TrayEvent::Menu(signal)))
+                }
             });
         }
         _ => {}
@@ -193,6 +305,13 @@ unsafe extern "system" fn tray_subclass_proc(hwnd: HWND, msg: u32, wparam: WPARA
     DefSubclassProc(hwnd, msg, wparam, lparam)
 }
 
+fn downcast_signal<T: Clone + 'static>(signal: &dyn Any) -> T {
+    signal
+        .downcast_ref::<T>()
+        .expect("Signal has the wrong type")
+        .clone()
+}
+
 #[allow(non_snake_case)]
 pub fn LOWORD(dword: u32) -> u16 {
     (dword & 0xFFFF) as u16
@@ -249,6 +368,10 @@ fn get_instance_handle() -> HINSTANCE {
 }
 
 pub type PlatformError = windows_result::Error;
+
+pub fn os_error_kind(_err: &PlatformError) -> TrayErrorKind {
+    TrayErrorKind::Other
+}
 impl From<PlatformError> for ErrorSource {
     fn from(value: PlatformError) -> Self {
         ErrorSource::Os(value)
@@ -0,0 +1,91 @@
+use std::mem::zeroed;
+use std::ptr::{null, null_mut};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Instant;
+
+use windows_sys::Win32::Foundation::{BOOL, FALSE, TRUE};
+use windows_sys::Win32::System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT};
+use windows_sys::Win32::System::Threading::{GetCurrentThreadId, INFINITE};
+use windows_sys::Win32::UI::WindowsAndMessaging::{
+    DispatchMessageW, MsgWaitForMultipleObjects, PeekMessageW, PostThreadMessageW, TranslateMessage, MSG, PM_REMOVE, QS_ALLINPUT, WM_NULL, WM_QUIT
+};
+
+use crate::error::TrayResult;
+use crate::platform::windows::{error_check, NativeTrayIcon};
+use crate::run::RunLoop;
+use crate::{forward_events, TrayIcon, TrayIconBuilder};
+
+static LOOP_THREAD: AtomicU32 = AtomicU32::new(0);
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub fn run<T: Clone + Send + 'static>(builder: TrayIconBuilder<T>, mut state: RunLoop<T>) -> TrayResult<()> {
+    let (sender, events) = flume::unbounded();
+    let tray = TrayIcon(NativeTrayIcon::new(builder, forward_events(sender))?);
+    let _ctrl_handler = CtrlHandler::install()?;
+    state.start(&tray);
+
+    let mut msg: MSG = unsafe { zeroed() };
+    while !state.exiting() {
+        let timeout = state
+            .next_deadline()
+            .map(|deadline| {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                remaining
+                    .as_micros()
+                    .div_ceil(1000)
+                    .min(INFINITE as u128 - 1) as u32
+            })
+            .unwrap_or(INFINITE);
+        unsafe { MsgWaitForMultipleObjects(0, null(), FALSE, timeout, QS_ALLINPUT) };
+
+        while unsafe { PeekMessageW(&mut msg, null_mut(), 0, 0, PM_REMOVE) } != FALSE {
+            if msg.message == WM_QUIT {
+                return Ok(());
+            }
+            unsafe {
+                TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+        if STOP_REQUESTED.swap(false, Ordering::AcqRel) {
+            log::debug!("Received console control event, stopping the tray");
+            break;
+        }
+        while let (false, Ok(event)) = (state.exiting(), events.try_recv()) {
+            state.handle_event(&tray, event);
+        }
+        state.fire_timers(&tray);
+    }
+    Ok(())
+}
+
+/// Wakes up the loop when `Ctrl+C` is pressed or the console gets closed
+struct CtrlHandler;
+
+impl CtrlHandler {
+    fn install() -> TrayResult<Self> {
+        LOOP_THREAD.store(unsafe { GetCurrentThreadId() }, Ordering::Release);
+        STOP_REQUESTED.store(false, Ordering::Release);
+        error_check(unsafe { SetConsoleCtrlHandler(Some(ctrl_handler), TRUE) })?;
+        Ok(Self)
+    }
+}
+
+impl Drop for CtrlHandler {
+    fn drop(&mut self) {
+        if let Err(err) = error_check(unsafe { SetConsoleCtrlHandler(Some(ctrl_handler), FALSE) }) {
+            log::warn!("Failed to remove console control handler: {err}");
+        }
+    }
+}
+
+unsafe extern "system" fn ctrl_handler(ctrl_type: u32) -> BOOL {
+    match ctrl_type {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT | CTRL_CLOSE_EVENT => {
+            STOP_REQUESTED.store(true, Ordering::Release);
+            PostThreadMessageW(LOOP_THREAD.load(Ordering::Acquire), WM_NULL, 0, 0);
+            TRUE
+        }
+        _ => FALSE
+    }
+}
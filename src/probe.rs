@@ -0,0 +1,46 @@
+#[cfg(target_os = "linux")]
+use crate::platform;
+use crate::TrayResult;
+
+/// Whether tray icons can be shown on the current desktop, see [probe]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Support {
+    /// Trays can be created and are shown
+    Available,
+    /// Trays can be created, but nothing shows them at the moment. *Linux only*
+    NoHost,
+    /// There is no `StatusNotifierWatcher` to register trays with, so creating one fails. *Linux only*
+    NoWatcher
+}
+
+/// Checks whether tray icons can be shown before creating one
+///
+/// On *Linux* this looks for a `StatusNotifierWatcher` on the session bus and asks it whether a host is registered.
+/// An error means that the session bus isn't reachable. The other platforms always report [Support::Available].
+///
+/// ```no_run
+/// match betrayer::probe()? {
+///     betrayer::Support::Available => println!("Trays are supported"),
+///     other => println!("Trays won't be visible: {other:?}")
+/// }
+/// # Ok::<(), betrayer::TrayError>(())
+/// ```
+pub fn probe() -> TrayResult<Support> {
+    #[cfg(target_os = "linux")]
+    return platform::probe();
+    #[cfg(not(target_os = "linux"))]
+    Ok(Support::Available)
+}
+
+/// Like [probe], but doesn't block the current thread
+pub async fn probe_async() -> TrayResult<Support> {
+    #[cfg(target_os = "linux")]
+    return platform::probe_async().await;
+    #[cfg(not(target_os = "linux"))]
+    Ok(Support::Available)
+}
+
+/// Whether [probe] reports [Support::Available], errors count as not supported
+pub fn is_supported() -> bool {
+    matches!(probe(), Ok(Support::Available))
+}
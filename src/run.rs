@@ -0,0 +1,376 @@
+// The loop is not implemented on MacOS yet
+#![cfg_attr(target_os = "macos", allow(dead_code))]
+
+use std::time::{Duration, Instant};
+
+use crate::{platform, TrayEvent, TrayIcon, TrayIconBuilder, TrayResult};
+
+/// Creates the tray icon and runs an event loop on the current thread until [RunControl::exit] is called
+///
+/// This is meant for small tray utilities that don't need any other event loop. The `handler` is called for every event
+/// and gets a [RunControl] to update the tray, schedule timers and exit the loop.
+/// The loop also stops when the process receives `SIGINT` or `SIGTERM` (`Ctrl+C` or closing the console on Windows).
+/// The tray is removed before this function returns.
+///
+/// On *Linux* the DBus connection of the tray is driven by the calling thread instead of a background thread.
+/// With the `tokio` feature a single-threaded tokio runtime is created for this, so it can't be called from within a tokio runtime.
+/// The signal handlers can't be uninstalled and stay in place after the loop returns. Signals that weren't handled before the
+/// first loop started keep their default action, so applications that handle them themselves should install their handlers first.
+///
+/// Not supported on *MacOS* yet, where it returns an error of kind [Unsupported](crate::TrayErrorKind::Unsupported)
+/// without creating the tray. Use the run loop of your own `NSApplication` (for example through `winit`) there.
+///
+/// ```no_run
+/// # use betrayer::{menu, TrayEvent, TrayIconBuilder};
+/// #[derive(Clone)]
+/// enum Signal {
+///     Quit
+/// }
+///
+/// let builder = TrayIconBuilder::new().with_menu(menu![button("Quit", Signal::Quit)]);
+/// betrayer::run(builder, |event, ctl| {
+///     if let TrayEvent::Menu(Signal::Quit) = event {
+///         ctl.exit();
+///     }
+/// })?;
+/// # Ok::<(), betrayer::TrayError>(())
+/// ```
+pub fn run<T, F>(builder: TrayIconBuilder<T>, handler: F) -> TrayResult<()>
+where
+    T: Clone + Send + 'static,
+    F: FnMut(TrayEvent<T>, &mut RunControl<T>) + 'static
+{
+    run_with_init(builder, |_| {}, handler)
+}
+
+/// Like [run], but calls `init` once the tray was created, for example to start timers
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use betrayer::TrayIconBuilder;
+/// let mut uptime = 0;
+/// betrayer::run_with_init(
+///     TrayIconBuilder::<()>::new(),
+///     move |ctl| {
+///         ctl.set_interval(Duration::from_secs(1), move |ctl| {
+///             uptime += 1;
+///             if let Err(err) = ctl.tray().set_tooltip(format!("Uptime: {uptime}s")) {
+///                 eprintln!("Failed to update the tooltip: {err}");
+///             }
+///         });
+///     },
+///     |_, _| {}
+/// )?;
+/// # Ok::<(), betrayer::TrayError>(())
+/// ```
+pub fn run_with_init<T, I, F>(builder: TrayIconBuilder<T>, init: I, handler: F) -> TrayResult<()>
+where
+    T: Clone + Send + 'static,
+    I: FnOnce(&mut RunControl<T>) + 'static,
+    F: FnMut(TrayEvent<T>, &mut RunControl<T>) + 'static
+{
+    platform::run(builder, RunLoop::new(init, handler))
+}
+
+/// Identifies a timer created by [RunControl::set_timeout] or [RunControl::set_interval]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct TimerId(u64);
+
+/// Gives the handler of [run] access to the tray and the event loop
+pub struct RunControl<'a, T> {
+    tray: &'a TrayIcon<T>,
+    timers: &'a mut Timers<T>,
+    exit: &'a mut bool
+}
+
+impl<T> RunControl<'_, T> {
+    /// The tray icon that is driven by the loop
+    pub fn tray(&self) -> &TrayIcon<T> {
+        self.tray
+    }
+
+    /// Stops the loop once the current handler returns
+    pub fn exit(&mut self) {
+        *self.exit = true;
+    }
+
+    /// Calls `callback` once after `delay`
+    pub fn set_timeout<F>(&mut self, delay: Duration, callback: F) -> TimerId
+    where
+        F: FnOnce(&mut RunControl<T>) + 'static
+    {
+        let mut callback = Some(callback);
+        self.timers.insert(delay, None, move |ctl| {
+            if let Some(callback) = callback.take() {
+                callback(ctl);
+            }
+        })
+    }
+
+    /// Calls `callback` every `period` until the timer is cancelled
+    pub fn set_interval<F>(&mut self, period: Duration, callback: F) -> TimerId
+    where
+        F: FnMut(&mut RunControl<T>) + 'static
+    {
+        self.timers.insert(period, Some(period), callback)
+    }
+
+    /// Stops a timer. Does nothing if the timer already finished.
+    pub fn cancel_timer(&mut self, id: TimerId) {
+        self.timers.cancel(id);
+    }
+}
+
+type TimerCallback<T> = Box<dyn FnMut(&mut RunControl<T>)>;
+
+struct Timer<T> {
+    id: TimerId,
+    deadline: Instant,
+    period: Option<Duration>,
+    callback: TimerCallback<T>
+}
+
+struct Timers<T> {
+    next_id: u64,
+    entries: Vec<Timer<T>>,
+    running: Option<TimerId>
+}
+
+impl<T> Timers<T> {
+    fn insert<F>(&mut self, delay: Duration, period: Option<Duration>, callback: F) -> TimerId
+    where
+        F: FnMut(&mut RunControl<T>) + 'static
+    {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.entries.push(Timer {
+            id,
+            deadline: Instant::now() + delay,
+            period,
+            callback: Box::new(callback)
+        });
+        id
+    }
+
+    fn cancel(&mut self, id: TimerId) {
+        self.entries.retain(|timer| timer.id != id);
+        if self.running == Some(id) {
+            self.running = None;
+        }
+    }
+
+    fn take_expired(&mut self, now: Instant) -> Option<Timer<T>> {
+        let (index, _) = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, timer)| timer.deadline <= now)
+            .min_by_key(|(_, timer)| timer.deadline)?;
+        let timer = self.entries.swap_remove(index);
+        self.running = Some(timer.id);
+        Some(timer)
+    }
+}
+
+/// The platform independent state of the loop started by [run]
+pub(crate) struct RunLoop<T> {
+    #[allow(clippy::type_complexity)]
+    init: Option<Box<dyn FnOnce(&mut RunControl<T>)>>,
+    #[allow(clippy::type_complexity)]
+    handler: Box<dyn FnMut(TrayEvent<T>, &mut RunControl<T>)>,
+    timers: Timers<T>,
+    exit: bool
+}
+
+impl<T> RunLoop<T> {
+    fn new<I, F>(init: I, handler: F) -> Self
+    where
+        I: FnOnce(&mut RunControl<T>) + 'static,
+        F: FnMut(TrayEvent<T>, &mut RunControl<T>) + 'static
+    {
+        Self {
+            init: Some(Box::new(init)),
+            handler: Box::new(handler),
+            timers: Timers {
+                next_id: 0,
+                entries: Vec::new(),
+                running: None
+            },
+            exit: false
+        }
+    }
+
+    /// Whether [RunControl::exit] was called
+    pub(crate) fn exiting(&self) -> bool {
+        self.exit
+    }
+
+    /// When the next timer is due, or `None` if there are no timers
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.timers.entries.iter().map(|timer| timer.deadline).min()
+    }
+
+    /// Runs the init callback, must be called once the tray was created
+    pub(crate) fn start(&mut self, tray: &TrayIcon<T>) {
+        if let Some(init) = self.init.take() {
+            let mut ctl = RunControl {
+                tray,
+                timers: &mut self.timers,
+                exit: &mut self.exit
+            };
+            init(&mut ctl);
+        }
+    }
+
+    pub(crate) fn handle_event(&mut self, tray: &TrayIcon<T>, event: TrayEvent<T>) {
+        let mut ctl = RunControl {
+            tray,
+            timers: &mut self.timers,
+            exit: &mut self.exit
+        };
+        (self.handler)(event, &mut ctl);
+    }
+
+    /// Runs the callbacks of all timers that are due
+    pub(crate) fn fire_timers(&mut self, tray: &TrayIcon<T>) {
+        let now = Instant::now();
+        while !self.exit {
+            let Some(mut timer) = self.timers.take_expired(now) else {
+                break;
+            };
+            let mut ctl = RunControl {
+                tray,
+                timers: &mut self.timers,
+                exit: &mut self.exit
+            };
+            (timer.callback)(&mut ctl);
+            if let (Some(period), true) = (timer.period, self.timers.running.take() == Some(timer.id)) {
+                // Skip ticks that were missed instead of firing them in a burst
+                timer.deadline += period;
+                if timer.deadline <= now {
+                    timer.deadline = now + period.max(Duration::from_millis(1));
+                }
+                self.timers.entries.push(timer);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux", feature = "async-io"))]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::platform::tests::serve;
+
+    type Log = Rc<RefCell<Vec<&'static str>>>;
+
+    fn run_loop(init: impl FnOnce(&mut RunControl<()>, Log) + 'static) -> (RunLoop<()>, Log) {
+        let log = Log::default();
+        let state = {
+            let log = log.clone();
+            RunLoop::new(move |ctl| init(ctl, log), |_, _| {})
+        };
+        (state, log)
+    }
+
+    fn wait_until(deadline: Option<Instant>) {
+        let deadline = deadline.expect("no timer is scheduled");
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    }
+
+    #[test]
+    fn timeouts_fire_once_in_order() {
+        let (native, _peer) = serve(TrayIconBuilder::<()>::new(), |_| {});
+        let tray = TrayIcon(native);
+        let (mut state, log) = run_loop(|ctl, log| {
+            let (first, second) = (log.clone(), log);
+            ctl.set_timeout(Duration::from_millis(40), move |_| second.borrow_mut().push("second"));
+            ctl.set_timeout(Duration::from_millis(20), move |_| first.borrow_mut().push("first"));
+        });
+        let start = Instant::now();
+        assert_eq!(state.next_deadline(), None);
+        state.start(&tray);
+        let deadline = state.next_deadline().unwrap();
+        assert!(deadline >= start + Duration::from_millis(20) && deadline < start + Duration::from_millis(40));
+
+        state.fire_timers(&tray);
+        assert!(log.borrow().is_empty());
+        std::thread::sleep(Duration::from_millis(50));
+        state.fire_timers(&tray);
+        assert_eq!(*log.borrow(), ["first", "second"]);
+        assert_eq!(state.next_deadline(), None);
+        state.fire_timers(&tray);
+        assert_eq!(log.borrow().len(), 2);
+    }
+
+    #[test]
+    fn intervals_repeat_until_cancelled() {
+        let (native, _peer) = serve(TrayIconBuilder::<()>::new(), |_| {});
+        let tray = TrayIcon(native);
+        let (mut state, log) = run_loop(|ctl, log| {
+            ctl.set_interval(Duration::from_millis(10), move |_| log.borrow_mut().push("tick"));
+        });
+        state.start(&tray);
+        for _ in 0..3 {
+            wait_until(state.next_deadline());
+            state.fire_timers(&tray);
+        }
+        assert_eq!(log.borrow().len(), 3);
+
+        // Missed ticks are skipped instead of being fired in a burst
+        std::thread::sleep(Duration::from_millis(55));
+        state.fire_timers(&tray);
+        assert_eq!(log.borrow().len(), 4);
+        assert!(state.next_deadline().unwrap() > Instant::now());
+    }
+
+    #[test]
+    fn timers_can_cancel_themselves_and_others() {
+        let (native, _peer) = serve(TrayIconBuilder::<()>::new(), |_| {});
+        let tray = TrayIcon(native);
+        let (mut state, log) = run_loop(|ctl, log| {
+            let cancelled = {
+                let log = log.clone();
+                ctl.set_timeout(Duration::from_millis(30), move |_| log.borrow_mut().push("cancelled"))
+            };
+            let own = Rc::new(RefCell::new(None));
+            let id = {
+                let own = own.clone();
+                ctl.set_interval(Duration::from_millis(10), move |ctl| {
+                    log.borrow_mut().push("interval");
+                    ctl.cancel_timer(cancelled);
+                    if let Some(id) = own.borrow_mut().take() {
+                        ctl.cancel_timer(id);
+                    }
+                })
+            };
+            *own.borrow_mut() = Some(id);
+        });
+        state.start(&tray);
+        wait_until(state.next_deadline());
+        state.fire_timers(&tray);
+        assert_eq!(*log.borrow(), ["interval"]);
+        assert_eq!(state.next_deadline(), None);
+    }
+
+    #[test]
+    fn exit_stops_firing_timers() {
+        let (native, _peer) = serve(TrayIconBuilder::<()>::new(), |_| {});
+        let tray = TrayIcon(native);
+        let (mut state, log) = run_loop(|ctl, log| {
+            let later = log.clone();
+            ctl.set_timeout(Duration::from_millis(10), move |ctl| {
+                log.borrow_mut().push("exit");
+                ctl.exit();
+            });
+            ctl.set_timeout(Duration::from_millis(20), move |_| later.borrow_mut().push("later"));
+        });
+        state.start(&tray);
+        std::thread::sleep(Duration::from_millis(30));
+        state.fire_timers(&tray);
+        assert!(state.exiting());
+        assert_eq!(*log.borrow(), ["exit"]);
+    }
+}
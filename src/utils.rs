@@ -1,4 +1,15 @@
 use std::cell::Cell;
+#[cfg(not(target_os = "linux"))]
+use std::future::Future;
+#[cfg(not(target_os = "linux"))]
+use std::sync::Arc;
+#[cfg(not(target_os = "linux"))]
+use std::task::{Context, Poll, Wake};
+#[cfg(not(target_os = "linux"))]
+use std::thread::Thread;
+
+#[cfg(not(target_os = "linux"))]
+use crate::{TrayError, TrayResult};
 
 #[allow(dead_code)]
 pub trait OptionCellExt<T> {
@@ -14,3 +25,37 @@ impl<T> OptionCellExt<T> for Cell<Option<T>> {
         })
     }
 }
+
+/// Runs `future` to completion on a new thread
+#[cfg(not(target_os = "linux"))]
+pub fn spawn_thread<F>(name: &str, future: F) -> TrayResult<()>
+where
+    F: Future<Output = ()> + Send + 'static
+{
+    std::thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || block_on(future))
+        .map_err(|err| TrayError::custom(format!("Failed to spawn {name} thread: {err}")))?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut future = std::pin::pin!(future);
+    let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park()
+        }
+    }
+}
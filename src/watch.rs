@@ -0,0 +1,301 @@
+//! Loading and hot reloading of menu definitions
+//!
+//! The parser is passed in by the caller, so any serde format works. JSON documents can be parsed directly with `serde_json::from_str`.
+//! TOML documents can't have an array at the root, so the menu has to be stored under a key:
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct Definition {
+//!     menu: Menu<String>
+//! }
+//!
+//! let parse = |s: &str| toml::from_str::<Definition>(s).map(|d| d.menu);
+//! let menu = load_menu("menu.toml", parse)?;
+//! ```
+//!
+//! ```toml
+//! [[menu]]
+//! type = "button"
+//! name = "Open"
+//! signal = "open"
+//!
+//! [[menu]]
+//! type = "separator"
+//!
+//! [[menu]]
+//! type = "menu"
+//! name = "Profiles"
+//! children = [
+//!     { type = "button", name = "Work", signal = "work", checked = true },
+//!     { type = "button", name = "Home", signal = "home", checked = false }
+//! ]
+//! ```
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+#[cfg(target_os = "linux")]
+use crate::TrayHandle;
+use crate::{Menu, TrayError, TrayResult};
+
+/// Reads a menu definition from `path` and parses it with `parse`
+pub fn load_menu<T, P, E>(path: impl AsRef<Path>, parse: P) -> TrayResult<Menu<T>>
+where
+    P: FnOnce(&str) -> Result<Menu<T>, E>,
+    E: Display
+{
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path).map_err(|err| TrayError::custom(format!("Failed to read {}: {err}", path.display())))?;
+    parse(&content).map_err(|err| TrayError::custom(format!("Failed to parse {}: {err}", path.display())))
+}
+
+/// Watches a menu definition file and hands every successfully parsed change to a callback
+///
+/// The file is polled on a background thread. Changes that fail to parse are logged and skipped, so the last valid menu stays active.
+/// On *Linux* [MenuWatcher::for_tray] applies the changes to a tray directly. There is no such helper on *Windows* and *MacOS*,
+/// as the tray must be updated from the thread that owns it. The callback has to forward the new menu to the event loop there,
+/// which then calls [TrayIcon::set_menu](crate::TrayIcon::set_menu).
+///
+/// ```ignore
+/// let proxy = event_loop.create_proxy();
+/// let _watcher = MenuWatcher::new("menu.json", Duration::from_secs(1), serde_json::from_str, move |menu| {
+///     let _ = proxy.send_event(UserEvent::MenuChanged(menu));
+/// })?;
+/// ```
+///
+/// Watching stops when the watcher is dropped.
+pub struct MenuWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl MenuWatcher {
+    /// Starts watching `path`, checking for changes every `interval`
+    pub fn new<T, P, E, F>(path: impl Into<PathBuf>, interval: Duration, parse: P, on_change: F) -> TrayResult<Self>
+    where
+        T: 'static,
+        P: Fn(&str) -> Result<Menu<T>, E> + Send + 'static,
+        E: Display,
+        F: FnMut(Menu<T>) + Send + 'static
+    {
+        Self::spawn(path.into(), interval, parse, on_change, |err| {
+            log::warn!("Keeping the current menu: {err}")
+        })
+    }
+
+    /// Starts watching `path` and sets every change as the menu of the tray behind `handle`. *Linux only*.
+    ///
+    /// On the other platforms use [MenuWatcher::new] and forward the menu to the thread that owns the tray.
+    /// Definitions that fail to parse and menus that can't be set are reported to the [error hook](crate::TrayIconBuilder::with_error_hook)
+    /// of the tray.
+    ///
+    /// ```ignore
+    /// let _watcher = MenuWatcher::for_tray("menu.json", Duration::from_secs(1), serde_json::from_str, tray.handle())?;
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn for_tray<T, P, E>(path: impl Into<PathBuf>, interval: Duration, parse: P, handle: TrayHandle<T>) -> TrayResult<Self>
+    where
+        T: Send + 'static,
+        P: Fn(&str) -> Result<Menu<T>, E> + Send + 'static,
+        E: Display
+    {
+        let on_error = handle.clone();
+        Self::spawn(
+            path.into(),
+            interval,
+            parse,
+            move |menu| {
+                if let Err(err) = handle.set_menu(menu) {
+                    handle.report(err);
+                }
+            },
+            move |err| on_error.report(err)
+        )
+    }
+
+    fn spawn<T, P, E, F, R>(path: PathBuf, interval: Duration, parse: P, mut on_change: F, on_error: R) -> TrayResult<Self>
+    where
+        T: 'static,
+        P: Fn(&str) -> Result<Menu<T>, E> + Send + 'static,
+        E: Display,
+        F: FnMut(Menu<T>) + Send + 'static,
+        R: Fn(TrayError) + Send + 'static
+    {
+        let mut last_modified = modification_time(&path).map_err(|err| TrayError::custom(format!("Failed to watch {}: {err}", path.display())))?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::Builder::new()
+                .name(String::from("betrayer menu watcher"))
+                .spawn(move || {
+                    log::trace!("Watching {} for menu changes", path.display());
+                    loop {
+                        std::thread::park_timeout(interval);
+                        if stop.load(Ordering::Acquire) {
+                            break;
+                        }
+                        match modification_time(&path) {
+                            Ok(modified) if modified != last_modified => {
+                                last_modified = modified;
+                                match load_menu(&path, &parse) {
+                                    Ok(menu) => {
+                                        log::debug!("Reloaded menu from {}", path.display());
+                                        on_change(menu);
+                                    }
+                                    Err(err) => on_error(err)
+                                }
+                            }
+                            Ok(_) => {}
+                            // Some editors replace the file when saving, so it can briefly disappear
+                            Err(err) => log::trace!("Failed to check {}: {err}", path.display())
+                        }
+                    }
+                })
+                .map_err(|err| TrayError::custom(format!("Failed to spawn watcher thread: {err}")))?
+        };
+        Ok(Self { stop, thread: Some(thread) })
+    }
+}
+
+impl Drop for MenuWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            if thread.join().is_err() {
+                log::warn!("The menu watcher thread panicked");
+            }
+        }
+    }
+}
+
+fn modification_time(path: &Path) -> std::io::Result<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    Ok((metadata.modified()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::MenuItem;
+
+    const TOML: &str = r#"
+        [[menu]]
+        type = "button"
+        name = "Open"
+        signal = "open"
+
+        [[menu]]
+        type = "separator"
+
+        [[menu]]
+        type = "menu"
+        name = "Profiles"
+        children = [
+            { type = "button", name = "Work", signal = "work", checked = true },
+            { type = "button", name = "Home", signal = "home", checked = false }
+        ]
+    "#;
+
+    const JSON: &str = r#"[
+        { "type": "button", "name": "Open", "signal": "open" },
+        { "type": "separator" },
+        { "type": "menu", "name": "Profiles", "children": [
+            { "type": "button", "name": "Work", "signal": "work", "checked": true },
+            { "type": "button", "name": "Home", "signal": "home", "checked": false }
+        ] }
+    ]"#;
+
+    #[derive(Deserialize)]
+    struct Definition {
+        menu: Menu<String>
+    }
+
+    fn expected() -> Menu<String> {
+        Menu::new([
+            MenuItem::button("Open", String::from("open")),
+            MenuItem::separator(),
+            MenuItem::menu(
+                "Profiles",
+                [
+                    MenuItem::check_button("Work", String::from("work"), true),
+                    MenuItem::check_button("Home", String::from("home"), false)
+                ]
+            )
+        ])
+    }
+
+    fn parse_json(s: &str) -> serde_json::Result<Menu<String>> {
+        serde_json::from_str(s)
+    }
+
+    fn parse_toml(s: &str) -> Result<Menu<String>, toml::de::Error> {
+        toml::from_str::<Definition>(s).map(|d| d.menu)
+    }
+
+    /// A file in a fresh directory that gets removed again when the test ends
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str, content: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("betrayer-watch-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(self.0.parent().unwrap());
+        }
+    }
+
+    #[test]
+    fn loads_toml_and_json() {
+        let toml = TestFile::new("menu.toml", TOML);
+        assert_eq!(load_menu(&toml.0, parse_toml).unwrap(), expected());
+        let json = TestFile::new("menu.json", JSON);
+        assert_eq!(load_menu(&json.0, parse_json).unwrap(), expected());
+    }
+
+    #[test]
+    fn reports_invalid_files() {
+        let file = TestFile::new("invalid.json", r#"[{ "type": "button", "name": "Open" }]"#);
+        let err = load_menu(&file.0, parse_json).unwrap_err();
+        assert!(err.to_string().contains("Failed to parse"), "{err}");
+        let err = load_menu(file.0.with_file_name("missing.json"), parse_json).unwrap_err();
+        assert!(err.to_string().contains("Failed to read"), "{err}");
+    }
+
+    #[test]
+    fn serialized_menus_round_trip() {
+        let json = serde_json::to_string(&expected()).unwrap();
+        assert_eq!(parse_json(&json).unwrap(), expected());
+    }
+
+    #[test]
+    fn watcher_reports_valid_changes() {
+        let file = TestFile::new("watched.json", "[]");
+        let (sender, changes) = mpsc::channel();
+        let _watcher = MenuWatcher::new(&file.0, Duration::from_millis(10), parse_json, move |menu| {
+            let _ = sender.send(menu);
+        })
+        .unwrap();
+        // The size is part of the change detection, so the changes are picked up even within the mtime granularity
+        std::fs::write(&file.0, "[ invalid").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        std::fs::write(&file.0, JSON).unwrap();
+        assert_eq!(changes.recv_timeout(Duration::from_secs(5)).unwrap(), expected());
+        assert!(changes.try_recv().is_err());
+    }
+}